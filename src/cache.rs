@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+
+use crate::utils::{fnv1a, ProviderError};
+
+// What the response cache does with requests.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheMode {
+    // Every request goes to the model.
+    #[default]
+    Off,
+    // Stored responses are served, and responses to new requests are stored.
+    Record,
+    // Only stored responses are served. New requests fail, so runs stay deterministic.
+    Replay,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct CacheConfig {
+    pub dir: PathBuf,
+    #[serde(default)]
+    pub mode: CacheMode,
+}
+
+// Entry kept on disk. The request is kept to tell apart requests of the same hash.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Entry {
+    request: String,
+    response: String,
+}
+
+// On-disk cache of responses keyed on the serialized request body, one file per request.
+pub struct ResponseCache {
+    dir: PathBuf,
+    mode: CacheMode,
+}
+
+impl ResponseCache {
+    pub fn new(config: &CacheConfig) -> Self {
+        ResponseCache {
+            dir: config.dir.clone(),
+            mode: config.mode,
+        }
+    }
+
+    // Hash is only used for file names, entries are matched on the whole request. It is stable,
+    // as hashers of std may change between releases and orphan every recorded entry.
+    fn path(&self, request: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", fnv1a(request.as_bytes())))
+    }
+
+    // Stored response to the request. Fails on miss in replay mode.
+    pub fn get(&self, request: &str) -> Result<Option<Vec<u8>>, ProviderError> {
+        let path = self.path(request);
+        let entry = match read(&path) {
+            Ok(entry) => entry.filter(|e| e.request == request),
+            Err(e) => {
+                log::warn!("Ignore broken cache entry {}: {}", path.display(), e);
+                None
+            }
+        };
+        match (entry, self.mode) {
+            (Some(entry), _) => Ok(Some(entry.response.into_bytes())),
+            (None, CacheMode::Replay) => Err(ProviderError::Cache(format!(
+                "no response recorded for the request in {}",
+                self.dir.display()
+            ))),
+            (None, _) => Ok(None),
+        }
+    }
+
+    pub fn put(&self, request: &str, response: &[u8]) -> Result<(), ProviderError> {
+        if self.mode != CacheMode::Record {
+            return Ok(());
+        }
+        let path = self.path(request);
+        let entry = Entry {
+            request: request.to_string(),
+            response: String::from_utf8_lossy(response).into_owned(),
+        };
+        std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&path, serde_json::to_vec(&entry)?))
+            .map_err(|e| ProviderError::Cache(format!("Write {}: {}", path.display(), e)))
+    }
+}
+
+fn read(path: &Path) -> Result<Option<Entry>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_slice(&std::fs::read(path)?)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_then_replay() {
+        let dir = std::env::temp_dir().join(format!("cache-{}", std::process::id()));
+        let config = |mode| CacheConfig {
+            dir: dir.clone(),
+            mode,
+        };
+
+        let record = ResponseCache::new(&config(CacheMode::Record));
+        assert_eq!(record.get("a").unwrap(), None);
+        record.put("a", b"{\"id\":\"1\"}").unwrap();
+
+        let replay = ResponseCache::new(&config(CacheMode::Replay));
+        assert_eq!(replay.get("a").unwrap(), Some(b"{\"id\":\"1\"}".to_vec()));
+        assert!(replay.get("b").is_err());
+        replay.put("b", b"{}").unwrap();
+        assert!(replay.get("b").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stable_file_names() {
+        // Reference values of FNV-1a, so recordings stay valid across toolchains.
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        let cache = ResponseCache::new(&CacheConfig {
+            dir: PathBuf::from("cache"),
+            mode: CacheMode::Replay,
+        });
+        assert_eq!(cache.path("a"), Path::new("cache/af63dc4c8601ec8c.json"));
+    }
+}
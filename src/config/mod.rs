@@ -1,10 +1,30 @@
-use crate::{model::Model, provider::Provider, utils::ProviderNotRegistered};
-use std::path::Path;
+use crate::{
+    cache::{CacheConfig, CacheMode, ResponseCache},
+    model::{Model, ModelBackend, Pricing, RoutePolicy},
+    provider::{
+        reasoning::Reasoning,
+        sampling::Sampling,
+        text_tools::{TextToolCalls, ToolMode},
+        Provider,
+    },
+    utils::ProviderNotRegistered,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[derive(serde::Deserialize)]
 pub struct Config {
     models: Vec<ModelParser>,
     services: Vec<ServiceParser>,
+    // Directory to keep JSONL transcripts of tasks. Not persisted if not set.
+    #[serde(default)]
+    transcript_dir: Option<PathBuf>,
+    // On-disk cache of responses shared by all models. Off if not set.
+    #[serde(default)]
+    cache: Option<CacheConfig>,
+    // Register models found on services with `discover` set, beside those listed here.
+    #[serde(default)]
+    auto_register: bool,
     // TODO: Add notifier to interact with human. Telegram bot, mail, CLI.
     // human_notifier: Vec<NotiferParser>,
 }
@@ -19,24 +39,79 @@ impl Config {
         Ok(config)
     }
 
+    pub fn transcript_dir(&self) -> Option<&Path> {
+        self.transcript_dir.as_deref()
+    }
+
+    pub fn services(&self) -> &[ServiceParser] {
+        &self.services
+    }
+
+    pub fn auto_register(&self) -> bool {
+        self.auto_register
+    }
+
+    fn cache(&self) -> Option<Arc<ResponseCache>> {
+        self.cache
+            .as_ref()
+            .filter(|c| c.mode != CacheMode::Off)
+            .map(|c| Arc::new(ResponseCache::new(c)))
+    }
+
+    // Model found on a service, with defaults for everything not discovered.
+    pub fn to_discovered_model(&self, name: &str, service: &ServiceParser) -> Model {
+        Model::new(name, service.clone()).with_cache(self.cache())
+    }
+
     pub fn to_models(&self) -> Result<Vec<Model>, Box<dyn std::error::Error>> {
         let services = self
             .services
             .iter()
             .map(|s| (s.name.clone(), s.clone()))
             .collect::<std::collections::HashMap<String, ServiceParser>>();
+        let service = |name: &str, model: &str| {
+            services.get(name).cloned().ok_or_else(|| {
+                ProviderNotRegistered::new(format!(
+                    "Service {} required by {} is not registered.",
+                    name, model
+                ))
+            })
+        };
+        let cache = self.cache();
         let mut models = Vec::new();
         for model_parser in &self.models {
+            // A model alias lists its backends. A plain model is served by a single provider.
+            let mut backends = Vec::new();
+            for backend in &model_parser.backends {
+                backends.push(ModelBackend {
+                    model: backend.model.clone(),
+                    provider: service(&backend.provider, &model_parser.name)?,
+                });
+            }
+            let provider = match (&model_parser.provider, backends.first()) {
+                (Some(provider), _) => service(provider, &model_parser.name)?,
+                (None, Some(backend)) => backend.provider.clone(),
+                (None, None) => {
+                    return Err(ProviderNotRegistered::new(format!(
+                        "Model {} has neither provider nor backends.",
+                        model_parser.name
+                    ))
+                    .into())
+                }
+            };
             models.push(
-                services
-                    .get(&model_parser.provider)
-                    .ok_or_else(|| {
-                        ProviderNotRegistered::new(format!(
-                            "Service {} required by {} is not registered.",
-                            model_parser.provider, model_parser.name
-                        ))
-                    })
-                    .map(|s| Model::new(&model_parser.name, (*s).clone()))?,
+                Model::new(&model_parser.name, provider)
+                    .with_backends(backends, model_parser.policy)
+                    .with_max_concurrency(model_parser.max_concurrency)
+                    .with_context_window(model_parser.context_window)
+                    .with_summarizer(model_parser.summarizer.clone())
+                    .with_stream(model_parser.stream)
+                    .with_sampling(model_parser.sampling.clone())
+                    .with_reasoning(model_parser.reasoning.clone())
+                    .with_text_tool_calls(model_parser.text_tool_calls)
+                    .with_tool_mode(model_parser.tool_mode)
+                    .with_pricing(model_parser.pricing)
+                    .with_cache(cache.clone()),
             )
         }
         Ok(models)
@@ -46,8 +121,42 @@ impl Config {
 #[derive(serde::Deserialize)]
 struct ModelParser {
     pub name: String,
+    #[serde(default)]
+    pub provider: Option<String>,
+    // Backends serving the model when it is an alias. Tried in order of the policy.
+    #[serde(default)]
+    pub backends: Vec<BackendParser>,
+    #[serde(default)]
+    pub policy: RoutePolicy,
+    #[serde(default = "default_max_concurrency")]
+    pub max_concurrency: usize,
+    #[serde(default)]
+    pub context_window: Option<u64>,
+    #[serde(default)]
+    pub summarizer: Option<String>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub sampling: Sampling,
+    #[serde(default)]
+    pub reasoning: Reasoning,
+    #[serde(default)]
+    pub text_tool_calls: TextToolCalls,
+    #[serde(default)]
+    pub tool_mode: ToolMode,
+    // Price per million tokens, to account cost of tasks.
+    #[serde(default)]
+    pub pricing: Option<Pricing>,
+}
+
+#[derive(serde::Deserialize)]
+struct BackendParser {
     pub provider: String,
+    pub model: String,
 }
 
-type ServiceParser = Provider;
+fn default_max_concurrency() -> usize {
+    1
+}
 
+type ServiceParser = Provider;
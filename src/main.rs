@@ -1,3 +1,4 @@
+mod cache;
 mod config;
 mod model;
 mod provider;
@@ -6,19 +7,66 @@ mod task;
 mod tool;
 mod utils;
 
+use std::path::PathBuf;
+
+use clap::Parser;
 use config::Config;
-use runtime::Runtime;
+use runtime::{
+    history::{self, Transcript},
+    Runtime,
+};
 use task::Task;
 use utils::log_init;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    log_init();
-    let config = Config::from_file("")?;
-    let mut runtime = Runtime::init(config)?;
-    // Add and spawn tasks.
-    let task = Task::from_path("")?;
-    runtime.new_task(task);
+#[derive(Parser)]
+struct Args {
+    /// Path to the runtime config.
+    #[arg(short, long, default_value = "src/config/config.json")]
+    config: PathBuf,
+    /// Task files to be scheduled.
+    #[arg(required_unless_present_any = ["replay", "resume"])]
+    tasks: Vec<PathBuf>,
+    /// Resume unfinished tasks from checkpoints in the transcript directory.
+    #[arg(long)]
+    resume: bool,
+    /// Print the conversation reconstructed from a task transcript and exit.
+    #[arg(long)]
+    replay: Option<PathBuf>,
+}
 
-    // TODO: Use models.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    log_init();
+    let args = Args::parse();
+    if let Some(path) = &args.replay {
+        for message in history::conversation(&Transcript::load(path)?)? {
+            println!("[{:?}] {}", message.role, message.content);
+            for tool_call in message.tool_calls.iter().flatten() {
+                println!(
+                    "  -> {} {}: {}",
+                    tool_call.id, tool_call.function.name, tool_call.function.arguments
+                );
+            }
+        }
+        return Ok(());
+    }
+    let config = Config::from_file(&args.config)?;
+    let mut runtime = Runtime::init(config).await?;
+    // Add tasks and run them to the end.
+    if args.resume {
+        runtime.resume()?;
+    }
+    for path in &args.tasks {
+        runtime.new_task(Task::from_path(path)?)?;
+    }
+    runtime.run().await;
+    for (name, is_success) in runtime.results() {
+        log::info!("Task {} ended with status: {}", name, is_success);
+    }
+    for (name, usage) in runtime.usage() {
+        if usage.total_tokens() > 0 {
+            log::info!("Model {} used {}", name, usage);
+        }
+    }
     Ok(())
 }
@@ -1,13 +1,125 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+
+use futures::{Stream, StreamExt};
+
 use crate::{
+    cache::ResponseCache,
     config::Config,
-    provider::{Provider, Request},
+    provider::{
+        reasoning::Reasoning,
+        sampling::Sampling,
+        stream::StreamEvent,
+        text_tools::{TextToolCalls, ToolMode},
+        Provider, Request, Response, Usage,
+    },
     utils::ProviderError,
 };
 
+// Order backends of a model are tried in. The next one is tried whenever one fails.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutePolicy {
+    // Always in the listed order.
+    #[default]
+    Failover,
+    // Starting from the next backend on every request.
+    RoundRobin,
+    // Fastest backend on recent requests first.
+    LeastLatency,
+}
+
+// Model served by a service under the given name.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ModelBackend {
+    pub model: String,
+    pub provider: Provider,
+}
+
+// Weight of the latest request in the moving average of latency.
+const LATENCY_WEIGHT: f64 = 0.3;
+// Latency counted for a failed request.
+const FAILURE_LATENCY: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct Router {
+    next: AtomicUsize,
+    // Moving average of latency by backend. None until tried.
+    latencies: Mutex<Vec<Option<Duration>>>,
+}
+
+// Price of a model in any currency, per million tokens.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Pricing {
+    #[serde(default)]
+    pub prompt: f64,
+    #[serde(default)]
+    pub completion: f64,
+}
+
+// Tokens used so far and what they cost. Cost stays 0 without pricing.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost: f64,
+}
+
+impl TokenUsage {
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.cost += other.cost;
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl std::fmt::Display for TokenUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} prompt + {} completion tokens, cost {:.4}",
+            self.prompt_tokens, self.completion_tokens, self.cost
+        )
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Model {
-    name: String, // More to go.
-    provider: Provider,
+    // Name tasks refer to. An alias if backends serve the model under other names.
+    name: String,
+    backends: Vec<ModelBackend>,
+    policy: RoutePolicy,
+    #[serde(skip)]
+    router: Router,
+    // Max iterations of different tasks running on this model at the same time.
+    max_concurrency: usize,
+    // Context window in tokens. History is compacted when the prompt approaches it.
+    context_window: Option<u64>,
+    // Model to summarize history on compaction. The model itself if not set.
+    summarizer: Option<String>,
+    // Stream responses to watch the progress of long generation.
+    stream: bool,
+    // Sampling of requests. Parameters set by a task take precedence.
+    sampling: Sampling,
+    // Tags delimiting reasoning in content, and whether reasoning is sent back.
+    reasoning: Reasoning,
+    // Tool calls written as text in content are taken as well, for models not filling the field.
+    text_tool_calls: TextToolCalls,
+    tool_mode: ToolMode,
+    pricing: Option<Pricing>,
+    // Usage of all tasks on this model.
+    #[serde(skip)]
+    usage: Mutex<TokenUsage>,
+    // Responses are served from it instead of the model if set.
+    #[serde(skip)]
+    cache: Option<Arc<ResponseCache>>,
 }
 
 impl Model {
@@ -15,11 +127,133 @@ impl Model {
     pub fn new(name: &str, provider: Provider) -> Self {
         Model {
             name: name.to_string(),
-            provider,
+            backends: vec![ModelBackend {
+                model: name.to_string(),
+                provider,
+            }],
+            policy: RoutePolicy::default(),
+            router: Router::default(),
+            max_concurrency: 1,
+            context_window: None,
+            summarizer: None,
+            stream: false,
+            sampling: Sampling::default(),
+            reasoning: Reasoning::default(),
+            text_tool_calls: TextToolCalls::default(),
+            tool_mode: ToolMode::default(),
+            pricing: None,
+            usage: Mutex::default(),
+            cache: None,
+        }
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+    }
+
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    pub fn context_window(&self) -> Option<u64> {
+        self.context_window
+    }
+
+    pub fn with_context_window(mut self, context_window: Option<u64>) -> Self {
+        self.context_window = context_window;
+        self
+    }
+
+    pub fn summarizer(&self) -> Option<&str> {
+        self.summarizer.as_deref()
+    }
+
+    pub fn with_summarizer(mut self, summarizer: Option<String>) -> Self {
+        self.summarizer = summarizer;
+        self
+    }
+
+    pub fn stream(&self) -> bool {
+        self.stream
+    }
+
+    pub fn with_stream(mut self, stream: bool) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    pub fn sampling(&self) -> &Sampling {
+        &self.sampling
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    pub fn reasoning(&self) -> &Reasoning {
+        &self.reasoning
+    }
+
+    pub fn with_reasoning(mut self, reasoning: Reasoning) -> Self {
+        self.reasoning = reasoning;
+        self
+    }
+
+    // Calls are always parsed from text when tools are offered in the prompt.
+    pub fn text_tool_calls(&self) -> TextToolCalls {
+        match (self.tool_mode, self.text_tool_calls) {
+            (ToolMode::Prompt, TextToolCalls::Off) => TextToolCalls::Hermes,
+            (_, parser) => parser,
         }
     }
 
-    // TODO: Try to adopt cache.
+    pub fn with_text_tool_calls(mut self, text_tool_calls: TextToolCalls) -> Self {
+        self.text_tool_calls = text_tool_calls;
+        self
+    }
+
+    pub fn tool_mode(&self) -> ToolMode {
+        self.tool_mode
+    }
+
+    pub fn with_tool_mode(mut self, tool_mode: ToolMode) -> Self {
+        self.tool_mode = tool_mode;
+        self
+    }
+
+    pub fn with_pricing(mut self, pricing: Option<Pricing>) -> Self {
+        self.pricing = pricing;
+        self
+    }
+
+    // Account usage of a response to the model. Returns it priced, to account to the task.
+    pub fn record_usage(&self, usage: &Usage) -> TokenUsage {
+        let pricing = self.pricing.unwrap_or(Pricing {
+            prompt: 0.0,
+            completion: 0.0,
+        });
+        let used = TokenUsage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            cost: (usage.prompt_tokens as f64 * pricing.prompt
+                + usage.completion_tokens as f64 * pricing.completion)
+                / 1_000_000.0,
+        };
+        self.usage.lock().unwrap().add(&used);
+        used
+    }
+
+    pub fn usage(&self) -> TokenUsage {
+        *self.usage.lock().unwrap()
+    }
+
+    pub fn with_cache(mut self, cache: Option<Arc<ResponseCache>>) -> Self {
+        self.cache = cache;
+        self
+    }
+
     pub fn from_config(config: &Config) -> Result<Vec<Model>, Box<dyn std::error::Error>> {
         config.to_models()
     }
@@ -28,7 +262,270 @@ impl Model {
         &self.name
     }
 
+    pub fn backends(&self) -> &[ModelBackend] {
+        &self.backends
+    }
+
+    // Serve the model by several backends instead of the single one given to `new`.
+    pub fn with_backends(mut self, backends: Vec<ModelBackend>, policy: RoutePolicy) -> Self {
+        if !backends.is_empty() {
+            self.backends = backends;
+        }
+        self.policy = policy;
+        self
+    }
+
+    // Indexes of backends in the order to try them for the next request.
+    fn route(&self) -> Vec<usize> {
+        let n = self.backends.len();
+        match self.policy {
+            RoutePolicy::Failover => (0..n).collect(),
+            RoutePolicy::RoundRobin => {
+                let start = self.router.next.fetch_add(1, Ordering::Relaxed);
+                (0..n).map(|i| (start + i) % n).collect()
+            }
+            RoutePolicy::LeastLatency => {
+                let latencies = self.router.latencies.lock().unwrap();
+                let mut order: Vec<usize> = (0..n).collect();
+                // Untried backends first, so every one gets measured.
+                order.sort_by_key(|i| latencies.get(*i).copied().flatten().unwrap_or_default());
+                order
+            }
+        }
+    }
+
+    fn observe(&self, backend: usize, latency: Duration) {
+        let mut latencies = self.router.latencies.lock().unwrap();
+        if latencies.len() < self.backends.len() {
+            latencies.resize(self.backends.len(), None);
+        }
+        latencies[backend] = Some(match latencies[backend] {
+            Some(average) => {
+                average.mul_f64(1.0 - LATENCY_WEIGHT) + latency.mul_f64(LATENCY_WEIGHT)
+            }
+            None => latency,
+        });
+    }
+
+    // Response from cache if any, or from the backends of the model.
     pub async fn do_request<'a>(&self, request: &Request<'a>) -> Result<Vec<u8>, ProviderError> {
-        self.provider.do_request(request).await
+        let Some(cache) = &self.cache else {
+            return self.do_request_backends(request).await;
+        };
+        let key = request.format().await;
+        if let Some(bytes) = cache.get(&key)? {
+            return Ok(bytes);
+        }
+        let bytes = self.do_request_backends(request).await?;
+        if let Err(e) = cache.put(&key, &bytes) {
+            log::warn!("Response not cached: {}", e);
+        }
+        Ok(bytes)
+    }
+
+    async fn do_request_backends<'a>(
+        &self,
+        request: &Request<'a>,
+    ) -> Result<Vec<u8>, ProviderError> {
+        let mut last_error = None;
+        for i in self.route() {
+            let backend = &self.backends[i];
+            let start = Instant::now();
+            match backend
+                .provider
+                .do_request(&request.with_model(&backend.model))
+                .await
+            {
+                Ok(bytes) => {
+                    self.observe(i, start.elapsed());
+                    return Ok(bytes);
+                }
+                Err(e) => {
+                    self.observe(i, FAILURE_LATENCY);
+                    log::warn!(
+                        "Model {} failed on service {}: {}",
+                        self.name,
+                        backend.provider.name,
+                        e
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            ProviderError::Config(format!("model {} has no backend", self.name))
+        }))
+    }
+
+    // Cached responses come whole as a single event. Streamed ones are stored once done.
+    pub async fn do_request_stream<'a>(
+        &self,
+        request: &Request<'a>,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, ProviderError>>, ProviderError> {
+        let Some(cache) = self.cache.clone() else {
+            return Ok(self
+                .do_request_stream_backends(request)
+                .await?
+                .left_stream()
+                .left_stream());
+        };
+        let key = request.format().await;
+        if let Some(bytes) = cache.get(&key)? {
+            let response = Response::from_u8(&bytes)
+                .map(StreamEvent::Done)
+                .map_err(|e| ProviderError::Decode(e.to_string()));
+            return Ok(futures::stream::once(async { response }).right_stream());
+        }
+        let events = self.do_request_stream_backends(request).await?;
+        Ok(events
+            .map(move |event| {
+                if let Ok(StreamEvent::Done(response)) = &event {
+                    let stored = serde_json::to_vec(response)
+                        .map_err(|e| ProviderError::Decode(e.to_string()))
+                        .and_then(|bytes| cache.put(&key, &bytes));
+                    if let Err(e) = stored {
+                        log::warn!("Response not cached: {}", e);
+                    }
+                }
+                event
+            })
+            .right_stream()
+            .left_stream())
+    }
+
+    // Backends are switched only until the stream starts. Latency is up to the first byte.
+    async fn do_request_stream_backends<'a>(
+        &self,
+        request: &Request<'a>,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, ProviderError>>, ProviderError> {
+        let mut last_error = None;
+        for i in self.route() {
+            let backend = &self.backends[i];
+            let start = Instant::now();
+            match backend
+                .provider
+                .do_request_stream(&request.with_model(&backend.model))
+                .await
+            {
+                Ok(events) => {
+                    self.observe(i, start.elapsed());
+                    return Ok(events);
+                }
+                Err(e) => {
+                    self.observe(i, FAILURE_LATENCY);
+                    log::warn!(
+                        "Model {} failed on service {}: {}",
+                        self.name,
+                        backend.provider.name,
+                        e
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            ProviderError::Config(format!("model {} has no backend", self.name))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::provider::mock;
+
+    fn backend(model: &str, addr: std::net::SocketAddr) -> ModelBackend {
+        ModelBackend {
+            model: model.to_string(),
+            provider: serde_json::from_value(json!({
+                "name": addr.to_string(),
+                "ip": addr.ip().to_string(),
+                "port": addr.port(),
+                "retry": { "max_retries": 0 }
+            }))
+            .unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_next_backend() {
+        // Nothing listens on a port just released.
+        let down = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (up, server) = mock::serve(vec![mock::Reply::status(200, "{}")]).await;
+        let model = Model::new("coder", backend("a", down).provider).with_backends(
+            vec![backend("a", down), backend("b", up)],
+            RoutePolicy::Failover,
+        );
+
+        let bytes = model
+            .do_request(&Request::new("coder".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(bytes, b"{}");
+        let captured = server.await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&captured[0].body).unwrap();
+        assert_eq!(body["model"], "b");
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_backends() {
+        let (first, first_server) = mock::serve(vec![mock::Reply::status(200, "{}")]).await;
+        let (second, second_server) = mock::serve(vec![mock::Reply::status(200, "{}")]).await;
+        let model = Model::new("coder", backend("a", first).provider).with_backends(
+            vec![backend("a", first), backend("b", second)],
+            RoutePolicy::RoundRobin,
+        );
+
+        let request = Request::new("coder".to_string());
+        model.do_request(&request).await.unwrap();
+        model.do_request(&request).await.unwrap();
+        assert_eq!(first_server.await.unwrap().len(), 1);
+        assert_eq!(second_server.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cached_responses() {
+        use crate::cache::{CacheConfig, CacheMode, ResponseCache};
+
+        let dir = std::env::temp_dir().join(format!("model-cache-{}", std::process::id()));
+        let cache = |mode| {
+            Some(Arc::new(ResponseCache::new(&CacheConfig {
+                dir: dir.clone(),
+                mode,
+            })))
+        };
+        let (addr, server) = mock::serve(vec![mock::Reply::json(json!({
+            "choices": [{ "message": { "role": "assistant", "content": "pong" } }]
+        }))])
+        .await;
+        let request = Request::new("m".to_string());
+
+        let recording =
+            Model::new("m", backend("m", addr).provider).with_cache(cache(CacheMode::Record));
+        let recorded = recording.do_request(&request).await.unwrap();
+        // Served from cache, the mock serves a single reply.
+        assert_eq!(recording.do_request(&request).await.unwrap(), recorded);
+        assert_eq!(server.await.unwrap().len(), 1);
+
+        let replaying =
+            Model::new("m", backend("m", addr).provider).with_cache(cache(CacheMode::Replay));
+        let mut events = std::pin::pin!(replaying.do_request_stream(&request).await.unwrap());
+        let Some(Ok(StreamEvent::Done(response))) = events.next().await else {
+            panic!("cached response should come as a single event");
+        };
+        assert_eq!(response.content(), "pong");
+        let other = Request::new("other".to_string());
+        assert!(matches!(
+            replaying.do_request(&other).await,
+            Err(ProviderError::Cache(_))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
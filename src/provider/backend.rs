@@ -0,0 +1,699 @@
+use serde_json::json;
+
+use super::{
+    call_id, Choice, FinishReason, Message, Request, Response, Roles, ToolCall, ToolCallFunction,
+    Usage,
+};
+use crate::utils::ProviderResponseError;
+
+// Max tokens to generate for APIs requiring it.
+const DEFAULT_MAX_TOKENS: u64 = 4096;
+
+// Kind of API a service speaks. Selected by `kind` of the service in config.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    // OpenAI compatible `/v1/chat/completions`, e.g. LM Studio, vLLM, llama.cpp.
+    #[default]
+    #[serde(alias = "openai")]
+    OpenAi,
+    // Ollama native `/api/chat`.
+    Ollama,
+    // Anthropic Messages API `/v1/messages`.
+    Anthropic,
+}
+
+impl ProviderKind {
+    pub fn backend(&self) -> &'static dyn ProviderBackend {
+        match self {
+            ProviderKind::OpenAi => &OpenAi,
+            ProviderKind::Ollama => &Ollama,
+            ProviderKind::Anthropic => &Anthropic,
+        }
+    }
+}
+
+// Translation between Request/Response and the wire format of an API.
+pub trait ProviderBackend: Send + Sync {
+    // Path of the chat endpoint.
+    fn path(&self) -> &'static str;
+    // Extra headers the API requires.
+    fn headers(&self) -> Vec<(&'static str, &'static str)> {
+        Vec::new()
+    }
+    // Header carrying the API key as is. `Authorization: Bearer` if none.
+    fn auth_header(&self) -> Option<&'static str> {
+        None
+    }
+    fn body(&self, request: &Request) -> serde_json::Value;
+    fn response(&self, bytes: &[u8]) -> Result<Response, ProviderResponseError>;
+    // Path listing models served.
+    fn models_path(&self) -> &'static str {
+        "/v1/models"
+    }
+    // Names of models in the listing, `{"data": [{"id": ...}]}` by default.
+    fn models(&self, bytes: &[u8]) -> Result<Vec<String>, ProviderResponseError> {
+        let listing: serde_json::Value =
+            serde_json::from_slice(bytes).map_err(|e| unmarshal_error(e, bytes))?;
+        model_names(&listing["data"], "id", bytes)
+    }
+    // Whether the API streams OpenAI compatible server sent events.
+    fn streams(&self) -> bool {
+        false
+    }
+}
+
+fn model_names(
+    list: &serde_json::Value,
+    key: &str,
+    bytes: &[u8],
+) -> Result<Vec<String>, ProviderResponseError> {
+    list.as_array()
+        .map(|models| {
+            models
+                .iter()
+                .filter_map(|m| m[key].as_str().map(str::to_string))
+                .collect()
+        })
+        .ok_or_else(|| {
+            ProviderResponseError::new(format!(
+                "Unexpected model listing: {}",
+                String::from_utf8_lossy(bytes)
+            ))
+        })
+}
+
+fn unmarshal_error(e: serde_json::Error, bytes: &[u8]) -> ProviderResponseError {
+    ProviderResponseError::new(format!(
+        "Unmarshal llm response error: {}.\nPretty print: {}",
+        e,
+        String::from_utf8_lossy(bytes),
+    ))
+}
+
+// Arguments are sent as objects by some APIs. Keep the raw string if it is not valid JSON.
+fn arguments_value(arguments: &str) -> serde_json::Value {
+    serde_json::from_str(arguments).unwrap_or_else(|_| json!(arguments))
+}
+
+fn arguments_string(arguments: &serde_json::Value) -> String {
+    match arguments {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn tool_call(id: String, name: String, arguments: &serde_json::Value) -> ToolCall {
+    ToolCall {
+        id,
+        tool_calls_type: "function".to_string(),
+        function: ToolCallFunction {
+            name,
+            arguments: arguments_string(arguments),
+        },
+    }
+}
+
+fn assistant(
+    content: String,
+    reasoning: Option<String>,
+    tool_calls: Vec<ToolCall>,
+    finish_reason: FinishReason,
+) -> Choice {
+    Choice {
+        index: 0,
+        finish_reason: Some(finish_reason),
+        message: Message {
+            role: Roles::Assistant,
+            content,
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            tool_call_id: None,
+            refusal: None,
+            reasoning_content: reasoning.filter(|r| !r.is_empty()),
+        },
+    }
+}
+
+pub struct OpenAi;
+
+impl ProviderBackend for OpenAi {
+    fn path(&self) -> &'static str {
+        "/v1/chat/completions"
+    }
+
+    fn body(&self, request: &Request) -> serde_json::Value {
+        request.body()
+    }
+
+    fn response(&self, bytes: &[u8]) -> Result<Response, ProviderResponseError> {
+        serde_json::from_slice(bytes).map_err(|e| unmarshal_error(e, bytes))
+    }
+
+    fn streams(&self) -> bool {
+        true
+    }
+}
+
+pub struct Ollama;
+
+#[derive(serde::Deserialize)]
+struct OllamaResponse {
+    #[serde(default)]
+    model: String,
+    message: OllamaMessage,
+    #[serde(default)]
+    done_reason: Option<String>,
+    #[serde(default)]
+    prompt_eval_count: u64,
+    #[serde(default)]
+    eval_count: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct OllamaMessage {
+    #[serde(default)]
+    content: String,
+    // Reasoning of thinking models.
+    #[serde(default)]
+    thinking: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+#[derive(serde::Deserialize)]
+struct OllamaToolCall {
+    function: OllamaFunction,
+}
+
+#[derive(serde::Deserialize)]
+struct OllamaFunction {
+    name: String,
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
+impl ProviderBackend for Ollama {
+    fn path(&self) -> &'static str {
+        "/api/chat"
+    }
+
+    fn models_path(&self) -> &'static str {
+        "/api/tags"
+    }
+
+    fn models(&self, bytes: &[u8]) -> Result<Vec<String>, ProviderResponseError> {
+        let listing: serde_json::Value =
+            serde_json::from_slice(bytes).map_err(|e| unmarshal_error(e, bytes))?;
+        model_names(&listing["models"], "name", bytes)
+    }
+
+    fn body(&self, request: &Request) -> serde_json::Value {
+        let messages: Vec<_> = request
+            .messages
+            .iter()
+            .map(|msg| {
+                let mut message = json!({
+                    "role": format!("{:?}", msg.role).to_lowercase(),
+                    "content": msg.content,
+                });
+                if let Some(tool_calls) = &msg.tool_calls {
+                    message["tool_calls"] = tool_calls
+                        .iter()
+                        .map(|tc| {
+                            json!({
+                                "function": {
+                                    "name": tc.function.name,
+                                    "arguments": arguments_value(&tc.function.arguments),
+                                }
+                            })
+                        })
+                        .collect();
+                }
+                message
+            })
+            .collect();
+        let tools: Vec<_> = request.tools.iter().map(|tool| tool.tooldoc()).collect();
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "stream": false,
+        });
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+        }
+        // Sampling goes to options. Tool choice is not supported.
+        let sampling = &request.sampling;
+        let mut options = json!({});
+        let params = [
+            ("temperature", json!(sampling.temperature)),
+            ("top_p", json!(sampling.top_p)),
+            ("num_predict", json!(sampling.max_tokens)),
+            ("stop", json!(sampling.stop)),
+            ("seed", json!(sampling.seed)),
+        ];
+        for (key, value) in params {
+            if !value.is_null() {
+                options[key] = value;
+            }
+        }
+        if options.as_object().is_some_and(|o| !o.is_empty()) {
+            body["options"] = options;
+        }
+        match &sampling.response_format {
+            Some(format) if format["type"] == "json_schema" => {
+                body["format"] = format["json_schema"]["schema"].clone()
+            }
+            Some(format) if format["type"] == "json_object" => body["format"] = json!("json"),
+            _ => {}
+        }
+        body
+    }
+
+    fn response(&self, bytes: &[u8]) -> Result<Response, ProviderResponseError> {
+        let response: OllamaResponse =
+            serde_json::from_slice(bytes).map_err(|e| unmarshal_error(e, bytes))?;
+        // Ollama gives no id to tool calls.
+        let tool_calls: Vec<_> = response
+            .message
+            .tool_calls
+            .into_iter()
+            .enumerate()
+            .map(|(i, tc)| tool_call(call_id(bytes, i), tc.function.name, &tc.function.arguments))
+            .collect();
+        let finish_reason = if !tool_calls.is_empty() {
+            FinishReason::ToolCalls
+        } else {
+            match response.done_reason.as_deref() {
+                Some("length") => FinishReason::Length,
+                _ => FinishReason::Stop,
+            }
+        };
+        Ok(Response {
+            id: String::new(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: response.model,
+            choices: vec![assistant(
+                response.message.content,
+                response.message.thinking,
+                tool_calls,
+                finish_reason,
+            )],
+            usage: Usage {
+                prompt_tokens: response.prompt_eval_count,
+                completion_tokens: response.eval_count,
+                total_tokens: response.prompt_eval_count + response.eval_count,
+            },
+            system_fingerprint: None,
+        })
+    }
+}
+
+pub struct Anthropic;
+
+#[derive(serde::Deserialize)]
+struct AnthropicResponse {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    model: String,
+    content: Vec<AnthropicBlock>,
+    #[serde(default)]
+    stop_reason: Option<String>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicBlock {
+    Text {
+        text: String,
+    },
+    Thinking {
+        thinking: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize)]
+struct AnthropicUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl ProviderBackend for Anthropic {
+    fn path(&self) -> &'static str {
+        "/v1/messages"
+    }
+
+    fn headers(&self) -> Vec<(&'static str, &'static str)> {
+        vec![("anthropic-version", "2023-06-01")]
+    }
+
+    fn auth_header(&self) -> Option<&'static str> {
+        Some("x-api-key")
+    }
+
+    fn body(&self, request: &Request) -> serde_json::Value {
+        // System prompts go apart from messages. Tool results are content blocks of user, and
+        // consecutive messages of the same role are merged into one.
+        let mut system = Vec::new();
+        let mut messages: Vec<serde_json::Value> = Vec::new();
+        for msg in request.messages.iter() {
+            let (role, mut blocks) = match msg.role {
+                Roles::System => {
+                    system.push(msg.content.clone());
+                    continue;
+                }
+                Roles::User => ("user", vec![]),
+                Roles::Assistant => ("assistant", vec![]),
+                Roles::Tool => (
+                    "user",
+                    vec![json!({
+                        "type": "tool_result",
+                        "tool_use_id": msg.tool_call_id.clone().unwrap_or_default(),
+                        "content": msg.content,
+                    })],
+                ),
+            };
+            if !matches!(msg.role, Roles::Tool) && !msg.content.trim().is_empty() {
+                blocks.push(json!({ "type": "text", "text": msg.content }));
+            }
+            for tc in msg.tool_calls.iter().flatten() {
+                blocks.push(json!({
+                    "type": "tool_use",
+                    "id": tc.id,
+                    "name": tc.function.name,
+                    "input": arguments_value(&tc.function.arguments),
+                }));
+            }
+            if blocks.is_empty() {
+                continue;
+            }
+            match messages.last_mut() {
+                Some(last) if last["role"] == role => {
+                    if let Some(content) = last["content"].as_array_mut() {
+                        content.extend(blocks);
+                    }
+                }
+                _ => messages.push(json!({ "role": role, "content": blocks })),
+            }
+        }
+        let tools: Vec<_> = request
+            .tools
+            .iter()
+            .map(|tool| {
+                let doc = tool.tooldoc();
+                json!({
+                    "name": doc["function"]["name"],
+                    "description": doc["function"]["description"],
+                    "input_schema": doc["function"]["parameters"],
+                })
+            })
+            .collect();
+        let sampling = &request.sampling;
+        let mut body = json!({
+            "model": request.model,
+            "max_tokens": sampling.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            "messages": messages,
+        });
+        if !system.is_empty() {
+            body["system"] = json!(system.join("\n\n"));
+        }
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+        }
+        // Seed and response format are not supported.
+        if let Some(temperature) = sampling.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = sampling.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(stop) = &sampling.stop {
+            body["stop_sequences"] = json!(stop);
+        }
+        let mut tool_choice = match &sampling.tool_choice {
+            Some(choice) if choice == "required" => json!({ "type": "any" }),
+            Some(choice) if choice == "none" => json!({ "type": "none" }),
+            Some(choice) if choice["type"] == "function" => {
+                json!({ "type": "tool", "name": choice["function"]["name"] })
+            }
+            _ => json!({ "type": "auto" }),
+        };
+        if sampling.parallel_tool_calls == Some(false) {
+            tool_choice["disable_parallel_tool_use"] = json!(true);
+        }
+        if sampling.tool_choice.is_some() || sampling.parallel_tool_calls.is_some() {
+            body["tool_choice"] = tool_choice;
+        }
+        body
+    }
+
+    fn response(&self, bytes: &[u8]) -> Result<Response, ProviderResponseError> {
+        let response: AnthropicResponse =
+            serde_json::from_slice(bytes).map_err(|e| unmarshal_error(e, bytes))?;
+        let mut content = String::new();
+        let mut reasoning = String::new();
+        let mut tool_calls = Vec::new();
+        for block in response.content {
+            match block {
+                AnthropicBlock::Thinking { thinking } => reasoning.push_str(&thinking),
+                AnthropicBlock::Text { text } => content.push_str(&text),
+                AnthropicBlock::ToolUse { id, name, input } => {
+                    tool_calls.push(tool_call(id, name, &input))
+                }
+                AnthropicBlock::Other => {}
+            }
+        }
+        let finish_reason = match response.stop_reason.as_deref() {
+            Some("tool_use") => FinishReason::ToolCalls,
+            Some("max_tokens") => FinishReason::Length,
+            Some("refusal") => FinishReason::ContentFilter,
+            _ => FinishReason::Stop,
+        };
+        let (prompt_tokens, completion_tokens) = response
+            .usage
+            .map(|u| (u.input_tokens, u.output_tokens))
+            .unwrap_or_default();
+        Ok(Response {
+            id: response.id,
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: response.model,
+            choices: vec![assistant(
+                content,
+                Some(reasoning),
+                tool_calls,
+                finish_reason,
+            )],
+            usage: Usage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+            },
+            system_fingerprint: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{mock, Provider};
+    use super::*;
+    use crate::tool::{shell::Shell, Tool, ToolBuilder};
+
+    fn conversation() -> Vec<Message> {
+        serde_json::from_value(json!([
+            { "role": "system", "content": "scratch paper" },
+            { "role": "user", "content": "list files" },
+            {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{
+                    "id": "call-1",
+                    "type": "function",
+                    "function": { "name": "shell", "arguments": "{\"executable\":\"ls\"}" }
+                }]
+            },
+            { "role": "tool", "content": "Cargo.toml", "tool_call_id": "call-1" }
+        ]))
+        .unwrap()
+    }
+
+    fn shell() -> Box<dyn Tool> {
+        Box::new(Into::<Shell>::into(ToolBuilder {
+            name: "shell".to_string(),
+            args: vec![],
+        }))
+    }
+
+    async fn round_trip(
+        kind: ProviderKind,
+        reply: serde_json::Value,
+    ) -> (mock::Captured, Response) {
+        let (addr, server) = mock::serve(vec![mock::Reply::json(reply)]).await;
+        let provider: Provider = serde_json::from_value(json!({
+            "name": "mock",
+            "ip": addr.ip().to_string(),
+            "port": addr.port(),
+            "kind": kind
+        }))
+        .unwrap();
+        let messages = conversation();
+        let tools = vec![shell()];
+        let request = Request::new("m".to_string())
+            .add_messages(&messages)
+            .add_tools(&tools);
+        let bytes = provider.do_request(&request).await.unwrap();
+        let captured = server.await.unwrap().pop().unwrap();
+        (captured, Response::from_u8(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_ollama_round_trip() {
+        let (captured, mut response) = round_trip(
+            ProviderKind::Ollama,
+            json!({
+                "model": "m",
+                "created_at": "2025-01-01T00:00:00Z",
+                "message": {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [{ "function": { "name": "shell", "arguments": { "executable": "pwd" } } }]
+                },
+                "done": true,
+                "done_reason": "stop",
+                "prompt_eval_count": 10,
+                "eval_count": 5
+            }),
+        )
+        .await;
+
+        assert_eq!(captured.path, "/api/chat");
+        let body: serde_json::Value = serde_json::from_str(&captured.body).unwrap();
+        assert_eq!(body["stream"], false);
+        assert_eq!(
+            body["messages"][2]["tool_calls"][0]["function"]["arguments"]["executable"],
+            "ls"
+        );
+        assert_eq!(body["messages"][3]["role"], "tool");
+
+        assert_eq!(response.usage().total_tokens, 15);
+        let tool_calls = response.tool_calls();
+        assert!(tool_calls[0].id.starts_with("call_"));
+        assert_eq!(tool_calls[0].function.arguments, "{\"executable\":\"pwd\"}");
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_round_trip() {
+        let (captured, mut response) = round_trip(
+            ProviderKind::Anthropic,
+            json!({
+                "id": "msg_1",
+                "type": "message",
+                "role": "assistant",
+                "model": "m",
+                "content": [
+                    { "type": "thinking", "thinking": "need pwd", "signature": "x" },
+                    { "type": "text", "text": "Checking." },
+                    { "type": "tool_use", "id": "toolu_1", "name": "shell", "input": { "executable": "pwd" } }
+                ],
+                "stop_reason": "tool_use",
+                "usage": { "input_tokens": 20, "output_tokens": 7 }
+            }),
+        )
+        .await;
+
+        assert_eq!(captured.path, "/v1/messages");
+        assert_eq!(captured.headers["anthropic-version"], "2023-06-01");
+        let body: serde_json::Value = serde_json::from_str(&captured.body).unwrap();
+        assert_eq!(body["system"], "scratch paper");
+        assert_eq!(body["tools"][0]["input_schema"]["type"], "object");
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+        assert_eq!(messages[1]["content"][0]["input"]["executable"], "ls");
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(messages[2]["content"][0]["tool_use_id"], "call-1");
+
+        assert_eq!(response.content(), "Checking.");
+        assert_eq!(
+            response.reasoning(&Default::default()).as_deref(),
+            Some("need pwd")
+        );
+        assert_eq!(response.usage().total_tokens, 27);
+        let tool_calls = response.tool_calls();
+        assert_eq!(tool_calls[0].id, "toolu_1");
+        assert_eq!(tool_calls[0].function.name, "shell");
+    }
+
+    #[tokio::test]
+    async fn test_openai_round_trip() {
+        let (captured, response) = round_trip(
+            ProviderKind::OpenAi,
+            json!({
+                "id": "c1",
+                "object": "chat.completion",
+                "created": 1,
+                "model": "m",
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": { "role": "assistant", "content": "done" }
+                }],
+                "usage": { "prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2 },
+                "system_fingerprint": "m"
+            }),
+        )
+        .await;
+
+        assert_eq!(captured.method, "POST");
+        assert_eq!(captured.path, "/v1/chat/completions");
+        assert_eq!(response.content(), "done");
+    }
+
+    #[test]
+    fn test_sampling_translation() {
+        let sampling: super::super::sampling::Sampling = serde_json::from_value(json!({
+            "temperature": 0.2,
+            "max_tokens": 100,
+            "stop": ["END"],
+            "seed": 7,
+            "tool_choice": "required",
+            "parallel_tool_calls": false,
+            "response_format": { "type": "json_object" }
+        }))
+        .unwrap();
+        let request = Request::new("m".to_string()).with_sampling(sampling);
+
+        let openai = OpenAi.body(&request);
+        assert_eq!(openai["seed"], 7);
+        assert_eq!(openai["tool_choice"], "required");
+
+        let ollama = Ollama.body(&request);
+        assert_eq!(ollama["options"]["num_predict"], 100);
+        assert_eq!(ollama["options"]["seed"], 7);
+        assert_eq!(ollama["format"], "json");
+
+        let anthropic = Anthropic.body(&request);
+        assert_eq!(anthropic["max_tokens"], 100);
+        assert_eq!(anthropic["stop_sequences"][0], "END");
+        assert_eq!(
+            anthropic["tool_choice"],
+            json!({ "type": "any", "disable_parallel_tool_use": true })
+        );
+
+        // No tools offered, no tools field, as some servers reject an empty one.
+        for body in [openai, ollama, anthropic] {
+            assert!(body.get("tools").is_none());
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use crate::utils::ProviderError;
+
+// Connection settings of the client shared by all requests to a service.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default)]
+pub struct HttpOptions {
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    // Max wait between two reads of the response. Slow generation streams keep going as long
+    // as chunks keep coming.
+    #[serde(default)]
+    pub read_timeout_ms: Option<u64>,
+    // Interval of TCP keep-alive probes.
+    #[serde(default)]
+    pub keep_alive_secs: Option<u64>,
+    // How long idle connections stay in the pool.
+    #[serde(default)]
+    pub idle_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub max_idle_connections: Option<usize>,
+    // Proxy URL for all requests, e.g. `http://proxy:3128`. System proxy settings apply if not set.
+    #[serde(default)]
+    pub proxy: Option<String>,
+}
+
+impl HttpOptions {
+    pub fn client(&self) -> Result<reqwest::Client, ProviderError> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(ms) = self.connect_timeout_ms {
+            builder = builder.connect_timeout(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.read_timeout_ms {
+            builder = builder.read_timeout(Duration::from_millis(ms));
+        }
+        if let Some(secs) = self.keep_alive_secs {
+            builder = builder.tcp_keepalive(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.idle_timeout_secs {
+            builder = builder.pool_idle_timeout(Duration::from_secs(secs));
+        }
+        if let Some(max) = self.max_idle_connections {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(proxy) = &self.proxy {
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|e| ProviderError::Config(format!("Invalid proxy {}: {}", proxy, e)))?;
+            builder = builder.proxy(proxy);
+        }
+        builder
+            .build()
+            .map_err(|e| ProviderError::Config(format!("Build http client: {}", e)))
+    }
+}
@@ -0,0 +1,1116 @@
+pub mod backend;
+pub mod http;
+#[cfg(test)]
+pub(crate) mod mock;
+pub mod reasoning;
+pub mod retry;
+pub mod sampling;
+pub mod stream;
+pub mod text_tools;
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+use futures::{Stream, StreamExt};
+use serde_json::json;
+
+use crate::{
+    tool::Tool,
+    utils::{fnv1a, ProviderError, ProviderResponseError},
+};
+use backend::ProviderKind;
+use http::HttpOptions;
+use reasoning::Reasoning;
+use retry::RetryPolicy;
+use sampling::Sampling;
+use stream::StreamEvent;
+use text_tools::TextToolCalls;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct Provider {
+    pub name: String,
+    #[serde(default)]
+    ip: String,
+    #[serde(default)]
+    port: u16,
+    // Full base URL, e.g. `https://gateway.example.com/llm`. Takes over ip and port if set.
+    #[serde(default)]
+    base_url: Option<String>,
+    // API spoken by the service. OpenAI compatible if not set.
+    #[serde(default)]
+    kind: ProviderKind,
+    // API key read from this environment variable, or else from this file.
+    #[serde(default)]
+    api_key_env: Option<String>,
+    #[serde(default)]
+    api_key_file: Option<PathBuf>,
+    // Header carrying the API key as is, e.g. `x-api-key`. Defaults to the one of the backend,
+    // `Authorization: Bearer` for most.
+    #[serde(default)]
+    api_key_header: Option<String>,
+    // Extra headers sent with every request.
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    retry: RetryPolicy,
+    #[serde(default)]
+    http: HttpOptions,
+    // Query models served at init, to validate configured models against them.
+    #[serde(default)]
+    discover: bool,
+    // Built on first request and shared by all clones, so models of the same service share
+    // the connection pool.
+    #[serde(skip)]
+    client: Arc<OnceLock<reqwest::Client>>,
+}
+
+impl Provider {
+    pub fn new(name: String, ip: String, port: u16) -> Self {
+        Provider {
+            name,
+            ip,
+            port,
+            base_url: None,
+            kind: ProviderKind::default(),
+            api_key_env: None,
+            api_key_file: None,
+            api_key_header: None,
+            headers: HashMap::new(),
+            retry: RetryPolicy::default(),
+            http: HttpOptions::default(),
+            discover: false,
+            client: Arc::default(),
+        }
+    }
+
+    // Base URL without trailing slash. Plain http on ip and port if no base URL is given.
+    fn base_url(&self) -> String {
+        match &self.base_url {
+            Some(base_url) => base_url.trim_end_matches('/').to_string(),
+            None => format!("http://{}:{}", self.ip, self.port),
+        }
+    }
+
+    // Read the API key on every request, so rotated keys are picked up without restart.
+    fn api_key(&self) -> Result<Option<String>, ProviderError> {
+        if let Some(env) = &self.api_key_env {
+            if let Ok(key) = std::env::var(env) {
+                return Ok(Some(key.trim().to_string()));
+            }
+            if self.api_key_file.is_none() {
+                return Err(ProviderError::Config(format!(
+                    "API key of service {} not found in environment variable {}",
+                    self.name, env
+                )));
+            }
+        }
+        match &self.api_key_file {
+            Some(file) => std::fs::read_to_string(file)
+                .map(|key| Some(key.trim().to_string()))
+                .map_err(|e| {
+                    ProviderError::Config(format!(
+                        "Read API key of service {} from {}: {}",
+                        self.name,
+                        file.display(),
+                        e
+                    ))
+                }),
+            None => Ok(None),
+        }
+    }
+
+    fn client(&self) -> Result<&reqwest::Client, ProviderError> {
+        if let Some(client) = self.client.get() {
+            return Ok(client);
+        }
+        let client = self.http.client()?;
+        Ok(self.client.get_or_init(|| client))
+    }
+
+    // Send the request, retrying on failures worth a retry within the budget of the service.
+    // Chat request with a body, or listing of models without.
+    async fn send(
+        &self,
+        body: Option<String>,
+        accept: &str,
+    ) -> Result<reqwest::Response, ProviderError> {
+        if let Some(body) = &body {
+            log::info!("Body: {}", body);
+        }
+        let mut attempt = 0;
+        loop {
+            let error = match self.send_once(body.clone(), accept).await {
+                Ok(response) => return Ok(response),
+                Err(e) => e,
+            };
+            if !error.retryable() || attempt >= self.retry.max_retries {
+                return Err(error);
+            }
+            let delay = self.retry.delay(attempt, error.retry_after());
+            log::warn!(
+                "Service {} failed, retry {}/{} in {:?}: {}",
+                self.name,
+                attempt + 1,
+                self.retry.max_retries,
+                delay,
+                error
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn send_once(
+        &self,
+        body: Option<String>,
+        accept: &str,
+    ) -> Result<reqwest::Response, ProviderError> {
+        let backend = self.kind.backend();
+        let client = self.client()?;
+        let mut builder = match body {
+            Some(body) => client
+                .post(format!("{}{}", self.base_url(), backend.path()))
+                .header("Content-Type", "application/json")
+                .body(body),
+            None => client.get(format!("{}{}", self.base_url(), backend.models_path())),
+        }
+        .header("Accept", accept);
+        for (name, value) in backend.headers() {
+            builder = builder.header(name, value);
+        }
+        if let Some(key) = self.api_key()? {
+            builder = match self.api_key_header.as_deref().or(backend.auth_header()) {
+                Some(header) => builder.header(header, key),
+                None => builder.bearer_auth(key),
+            };
+        }
+        for (name, value) in self.headers.iter() {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        let response = builder
+            .send()
+            .await
+            .map_err(|e| ProviderError::Transport(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let retry_after = retry::retry_after(response.headers());
+        let body = response.text().await.unwrap_or_default();
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err(ProviderError::RateLimited { retry_after, body });
+        }
+        Err(ProviderError::Status {
+            status: status.as_u16(),
+            // Only honored on 5xx, as other statuses are not retried anyway.
+            retry_after: retry_after.filter(|_| status.is_server_error()),
+            body,
+        })
+    }
+
+    // Names of models the service serves.
+    pub async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        let response = self.send(None, "application/json").await?;
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| ProviderError::Transport(e.to_string()))?;
+        self.kind
+            .backend()
+            .models(&bytes)
+            .map_err(|e| ProviderError::Decode(e.to_string()))
+    }
+
+    pub fn discover(&self) -> bool {
+        self.discover
+    }
+
+    // Feed the request to llm and get response, in the shape of OpenAI chat completion
+    // whatever the backend is.
+    pub async fn do_request<'a>(&self, request: &Request<'a>) -> Result<Vec<u8>, ProviderError> {
+        let backend = self.kind.backend();
+        let body = backend.body(request).to_string();
+        let response = self.send(Some(body), "application/json").await?;
+
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| ProviderError::Transport(e.to_string()))?;
+
+        if self.kind == ProviderKind::OpenAi {
+            return Ok(bytes.to_vec());
+        }
+        let response = backend
+            .response(&bytes)
+            .map_err(|e| ProviderError::Decode(e.to_string()))?;
+        serde_json::to_vec(&response).map_err(|e| ProviderError::Decode(e.to_string()))
+    }
+
+    // Feed the request to llm and get response as a stream of events. Backends not streaming
+    // yield the whole response at once.
+    pub async fn do_request_stream<'a>(
+        &self,
+        request: &Request<'a>,
+    ) -> Result<impl Stream<Item = Result<StreamEvent, ProviderError>>, ProviderError> {
+        let backend = self.kind.backend();
+        if !backend.streams() {
+            let bytes = self.do_request(request).await?;
+            let response = Response::from_u8(&bytes)
+                .map(StreamEvent::Done)
+                .map_err(|e| ProviderError::Decode(e.to_string()));
+            return Ok(futures::stream::once(async { response }).right_stream());
+        }
+        let body = request.format_stream().await;
+        let response = self.send(Some(body), "text/event-stream").await?;
+
+        Ok(stream::events(response.bytes_stream()).left_stream())
+    }
+}
+
+// Message and Roles in Response and Request.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct Message {
+    pub(crate) role: Roles,
+    // Some servers send null content along with tool calls.
+    #[serde(default, deserialize_with = "nullable")]
+    pub(crate) content: String,
+    // Tool calls requested by assistant. Sent back as is to keep the conversation intact.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tool_calls: Option<Vec<ToolCall>>,
+    // Only for tool role. Id of the tool call this message is the result of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tool_call_id: Option<String>,
+    // Only for assistant. Why the model declined to answer, instead of content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) refusal: Option<String>,
+    // Only for assistant. Reasoning returned apart from content by some servers. Never sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) reasoning_content: Option<String>,
+}
+
+impl Message {
+    // Plain message, without tool calls or anything only answers carry.
+    pub fn new(role: Roles, content: String) -> Self {
+        Message {
+            role,
+            content,
+            tool_calls: None,
+            tool_call_id: None,
+            refusal: None,
+            reasoning_content: None,
+        }
+    }
+}
+
+// Default for null as well as for missing fields.
+fn nullable<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + serde::Deserialize<'de>,
+{
+    <Option<T> as serde::Deserialize>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Roles {
+    User,
+    System,
+    Assistant,
+    Tool,
+}
+
+impl From<&str> for Roles {
+    fn from(value: &str) -> Self {
+        match value {
+            "user" | "User" => Roles::User,
+            "system" | "System" => Roles::System,
+            "assistant" | "Assistant" => Roles::Assistant,
+            "tool" | "Tool" => Roles::Tool,
+            _ => panic!("Invalid role"),
+        }
+    }
+}
+
+// Request to be made.
+#[derive(Clone)]
+pub struct Request<'a> {
+    model: String,
+    messages: Vec<&'a Message>,
+    tools: Vec<&'a dyn Tool>,
+    sampling: Sampling,
+}
+
+impl<'a> Request<'a> {
+    // Construct empty struct Request.
+    pub fn new(model: String) -> Self {
+        Request {
+            model,
+            messages: Vec::new(),
+            tools: Vec::new(),
+            sampling: Sampling::default(),
+        }
+    }
+
+    // Same request for the model under another name, as a service serving an alias knows it.
+    pub fn with_model(&self, model: &str) -> Self {
+        Request {
+            model: model.to_string(),
+            ..self.clone()
+        }
+    }
+
+    pub(crate) async fn format(&self) -> String {
+        self.body().to_string()
+    }
+
+    // Body asking for server sent events, with usage reported in the last chunk.
+    pub(crate) async fn format_stream(&self) -> String {
+        let mut body = self.body();
+        body["stream"] = json!(true);
+        body["stream_options"] = json!({ "include_usage": true });
+        body.to_string()
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    fn body(&self) -> serde_json::Value {
+        let tools: Vec<_> = self.tools.iter().map(|tool| tool.tooldoc()).collect();
+
+        let mut body = json!({
+            "model": self.model.clone(),
+            "messages": self.messages.iter().map(|msg| {
+                let mut message = json!({
+                    "role": format!("{:?}", msg.role).to_lowercase(),
+                    "content": msg.content
+                });
+                if let Some(tool_calls) = &msg.tool_calls {
+                    message["tool_calls"] = json!(tool_calls);
+                }
+                if let Some(tool_call_id) = &msg.tool_call_id {
+                    message["tool_call_id"] = json!(tool_call_id);
+                }
+                message
+            }).collect::<Vec<_>>(),
+        });
+        // Left out rather than empty, as some servers reject an empty list.
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+        }
+        self.sampling.apply(&mut body);
+        body
+    }
+
+    pub fn add_tool(mut self, tool: &'a dyn Tool) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    pub fn add_tools(mut self, tools: &'a [Box<dyn Tool>]) -> Self {
+        tools.iter().for_each(|tool| self.tools.push(tool.as_ref()));
+        self
+    }
+
+    // Add a single message.
+    pub fn add_message(mut self, message: &'a Message) -> Self {
+        self.messages.push(message);
+        self
+    }
+
+    // Add the whole conversation in order.
+    pub fn add_messages(mut self, messages: &'a [Message]) -> Self {
+        self.messages.extend(messages.iter());
+        self
+    }
+}
+
+// Response. Only choices are required, as servers differ in what else they send.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Response {
+    #[serde(default, deserialize_with = "nullable")]
+    id: String,
+    #[serde(default, deserialize_with = "nullable")]
+    object: String,
+    #[serde(default, deserialize_with = "nullable")]
+    created: u64,
+    #[serde(default, deserialize_with = "nullable")]
+    model: String,
+    choices: Vec<Choice>,
+    #[serde(default, deserialize_with = "nullable")]
+    usage: Usage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    system_fingerprint: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Choice {
+    #[serde(default)]
+    index: u64,
+    // Null until the generation ends, and on some servers even then.
+    #[serde(default)]
+    finish_reason: Option<FinishReason>,
+    message: Message,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    Stop,
+    // Cut by max tokens or the context window.
+    Length,
+    ToolCalls,
+    ContentFilter,
+    // Legacy function calling.
+    FunctionCall,
+    // Anything else servers come up with.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ToolCall {
+    // Empty if the server gave none. Filled in as the response is parsed.
+    #[serde(default, deserialize_with = "nullable")]
+    pub id: String,
+    #[serde(
+        rename = "type",
+        default = "function_type",
+        deserialize_with = "tool_call_type"
+    )]
+    tool_calls_type: String,
+    pub function: ToolCallFunction,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+// `function`, the only type there is, if missing or null.
+fn tool_call_type<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    <Option<String> as serde::Deserialize>::deserialize(deserializer)
+        .map(|t| t.unwrap_or_else(function_type))
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ToolCallFunction {
+    pub name: String,
+    pub arguments: String,
+}
+
+// Id for a tool call the server gave none, from the response it came in and its place there.
+// Derived rather than random, so requests following it stay the same for cached replays.
+pub(crate) fn call_id(response: &[u8], index: usize) -> String {
+    format!("call_{:016x}_{}", fnv1a(response), index)
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+impl Response {
+    pub fn from_u8(bytes: &[u8]) -> Result<Self, ProviderResponseError> {
+        let mut response: Response = serde_json::from_slice(bytes).map_err(|e| {
+            ProviderResponseError::new(format!(
+                "Unmarshal llm response error: {}.\nPretty print: {}",
+                e,
+                String::from_utf8_lossy(bytes).into_owned(),
+            ))
+        })?;
+        if response.choices.is_empty() {
+            return Err(ProviderResponseError::new(format!(
+                "Llm response has no choices: {}",
+                String::from_utf8_lossy(bytes).into_owned(),
+            )));
+        }
+        for choice in response.choices.iter_mut() {
+            for (i, tool_call) in choice.message.tool_calls.iter_mut().flatten().enumerate() {
+                if tool_call.id.is_empty() {
+                    tool_call.id = call_id(bytes, i);
+                }
+            }
+        }
+        Ok(response)
+    }
+}
+
+impl Response {
+    // Answer of the first choice. Servers return more only if asked to.
+    fn choice(&self) -> Option<&Choice> {
+        self.choices.first()
+    }
+
+    // Pure content. Thinking part trimmed.
+    //  e.g. <think>\nOkay ....beyond that.\n</think>\n\npong
+    //  ->
+    //  pong.
+    pub fn content(&self) -> String {
+        self.content_with(&Reasoning::default())
+    }
+
+    // Pure content, with reasoning delimited by the tags of the model trimmed.
+    pub fn content_with(&self, reasoning: &Reasoning) -> String {
+        reasoning.split(&self.full()).1
+    }
+
+    // Reasoning returned apart from content, or else delimited by tags in content.
+    pub fn reasoning(&self, reasoning: &Reasoning) -> Option<String> {
+        let message = &self.choice()?.message;
+        match &message.reasoning_content {
+            Some(r) if !r.trim().is_empty() => Some(r.trim().to_string()),
+            _ => reasoning.split(&message.content).0,
+        }
+    }
+
+    // Give full response.
+    pub fn full(&self) -> String {
+        self.choice()
+            .map(|c| c.message.content.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn usage(&self) -> &Usage {
+        &self.usage
+    }
+
+    pub fn finish_reason(&self) -> Option<FinishReason> {
+        self.choice().and_then(|c| c.finish_reason)
+    }
+
+    pub fn refusal(&self) -> Option<&str> {
+        self.choice().and_then(|c| c.message.refusal.as_deref())
+    }
+
+    // Message of the answer, to be put back to the conversation. Empty if there is no answer.
+    pub fn message(&self) -> Message {
+        self.choice()
+            .map(|c| c.message.clone())
+            .unwrap_or_else(|| Message::new(Roles::Assistant, String::new()))
+    }
+
+    // Message of the answer as the model is to see it again. Reasoning dropped if excluded.
+    pub fn message_with(&self, reasoning: &Reasoning) -> Message {
+        let mut message = self.message();
+        if reasoning.exclude_from_messages {
+            message.content = reasoning.split(&message.content).1;
+            message.reasoning_content = None;
+        }
+        message
+    }
+
+    // Move tool calls written as text in content into the structured field, unless it is
+    // filled already. Reasoning is left alone, as models think aloud about calls. Calls are
+    // given ids from `turn`, the iteration of the task, to be unique in the conversation.
+    pub fn parse_text_tool_calls(
+        &mut self,
+        parser: TextToolCalls,
+        reasoning: &Reasoning,
+        turn: usize,
+    ) {
+        let Some(choice) = self.choices.first_mut() else {
+            return;
+        };
+        let message = &mut choice.message;
+        if message.tool_calls.as_ref().is_some_and(|t| !t.is_empty()) {
+            return;
+        }
+        let answer = message
+            .content
+            .rfind(&reasoning.close_tag)
+            .map(|pos| pos + reasoning.close_tag.len())
+            .unwrap_or(0);
+        let Some((calls, rest)) = parser.extract(&message.content[answer..]) else {
+            return;
+        };
+        message.tool_calls = Some(
+            calls
+                .into_iter()
+                .enumerate()
+                .map(|(i, function)| ToolCall {
+                    id: format!("text_call_{}_{}", turn, i),
+                    tool_calls_type: "function".to_string(),
+                    function,
+                })
+                .collect(),
+        );
+        message.content = format!("{}{}", &message.content[..answer], rest);
+        choice.finish_reason = Some(FinishReason::ToolCalls);
+    }
+
+    // Give tool calls of the answer. Only the first choice is kept in the conversation, so
+    // calls of other choices would have no assistant message to answer to.
+    pub fn tool_calls(&mut self) -> Vec<ToolCall> {
+        self.choice()
+            .and_then(|c| c.message.tool_calls.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        model::Model,
+        tool::{shell::Shell, ToolBuilder},
+        utils::log_init,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_model_request() -> Result<(), Box<dyn std::error::Error>> {
+        log_init();
+
+        let model = Model::new(
+            "deepseek-r1-distill-qwen-14b@q4_k_m",
+            Provider::new("LM Studio".to_string(), "192.168.2.228".to_string(), 1234),
+        );
+        let message = &Message::new(
+            Roles::from("user"), // Role should be an enum.main
+            String::from("Do not choose any tools. Do not answer anything else. Just response \"pong\" only."),
+        );
+        let tool: Box<dyn Tool> = Box::new(Into::<Shell>::into(ToolBuilder {
+            name: "shell".to_string(),
+            args: vec![],
+        }));
+        let request = Request::new(model.name().to_string())
+            .add_message(message)
+            .add_tool(tool.as_ref());
+        let response = Response::from_u8(&(model.do_request(&request).await?))?;
+        // Trim reasoning part in the response.
+        assert_eq!(response.content(), "pong");
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_tool_call() {
+        let json_data = r#"
+    {
+      "id": "chatcmpl-idphs4avvdqc2yxofanzdb",
+      "object": "chat.completion",
+      "created": 1738763823,
+      "model": "deepseek-r1-distill-qwen-14b@q4_k_m",
+      "choices": [
+        {
+          "index": 0,
+          "finish_reason": "tool_calls",
+          "message": {
+            "role": "assistant",
+            "content": "",
+            "tool_calls": [
+              {
+                "id": "592365529",
+                "type": "function",
+                "function": {
+                  "name": "search_products",
+                  "arguments": "{\"query\":\"Dell products\",\"category\":\"electronics\",\"max_price\":50}"
+                }
+              }
+            ]
+          }
+        }
+      ],
+      "usage": {
+        "prompt_tokens": 445,
+        "completion_tokens": 221,
+        "total_tokens": 666
+      },
+      "system_fingerprint": "deepseek-r1-distill-qwen-14b@q4_k_m"
+    }
+    "#;
+
+        let response = Response::from_u8(json_data.as_bytes()).expect("Failed to deserialize");
+
+        assert_eq!(response.id, "chatcmpl-idphs4avvdqc2yxofanzdb");
+        assert_eq!(response.choices.len(), 1);
+        assert_eq!(response.finish_reason(), Some(FinishReason::ToolCalls));
+        assert_eq!(
+            response.choices[0]
+                .message
+                .tool_calls
+                .as_ref()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            response.choices[0].message.tool_calls.as_ref().unwrap()[0].id,
+            "592365529"
+        );
+        assert_eq!(
+            response.choices[0].message.tool_calls.as_ref().unwrap()[0].tool_calls_type,
+            "function"
+        );
+        assert_eq!(
+            response.choices[0].message.tool_calls.as_ref().unwrap()[0]
+                .function
+                .name,
+            "search_products"
+        );
+        assert_eq!(
+            response.choices[0].message.tool_calls.as_ref().unwrap()[0]
+                .function
+                .arguments,
+            "{\"query\":\"Dell products\",\"category\":\"electronics\",\"max_price\":50}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_format_tool_round_trip() {
+        let assistant = Message {
+            tool_calls: Some(vec![ToolCall {
+                id: "592365529".to_string(),
+                tool_calls_type: "function".to_string(),
+                function: ToolCallFunction {
+                    name: "shell".to_string(),
+                    arguments: "{\"executable\":\"ls\",\"args\":[\"-l\"]}".to_string(),
+                },
+            }]),
+            ..Message::new(Roles::Assistant, "".to_string())
+        };
+        let tool_result = Message {
+            tool_call_id: Some("592365529".to_string()),
+            ..Message::new(Roles::from("tool"), "{\"stdout\":\"\"}".to_string())
+        };
+        let request = Request::new("model".to_string())
+            .add_message(&assistant)
+            .add_message(&tool_result);
+        let body: serde_json::Value = serde_json::from_str(&request.format().await).unwrap();
+
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages[0]["role"], "assistant");
+        assert_eq!(messages[0]["tool_calls"][0]["id"], "592365529");
+        assert_eq!(messages[0]["tool_calls"][0]["type"], "function");
+        assert_eq!(messages[0]["tool_calls"][0]["function"]["name"], "shell");
+        assert!(messages[0].get("tool_call_id").is_none());
+        assert_eq!(messages[1]["role"], "tool");
+        assert_eq!(messages[1]["tool_call_id"], "592365529");
+        assert!(messages[1].get("tool_calls").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_base_url_and_auth() {
+        let (addr, server) = mock::serve(vec![
+            mock::Reply::status(200, "{}"),
+            mock::Reply::status(200, "{}"),
+            mock::Reply::status(200, "{}"),
+        ])
+        .await;
+        let key_file = std::env::temp_dir().join(format!("llm_demo_key_{}", std::process::id()));
+        std::fs::write(&key_file, "file-key\n").unwrap();
+        std::env::set_var("LLM_DEMO_TEST_API_KEY", "env-key");
+        let gateway: Provider = serde_json::from_value(json!({
+            "name": "gateway",
+            "base_url": format!("http://{}/llm/", addr),
+            "api_key_env": "LLM_DEMO_TEST_API_KEY",
+            "headers": { "X-Team": "agents" }
+        }))
+        .unwrap();
+        let anthropic: Provider = serde_json::from_value(json!({
+            "name": "anthropic",
+            "ip": addr.ip().to_string(),
+            "port": addr.port(),
+            "api_key_file": key_file,
+            "api_key_header": "x-api-key"
+        }))
+        .unwrap();
+
+        // The Anthropic backend takes its key in `x-api-key` unless told otherwise.
+        let anthropic_kind: Provider = serde_json::from_value(json!({
+            "name": "anthropic_kind",
+            "kind": "anthropic",
+            "ip": addr.ip().to_string(),
+            "port": addr.port(),
+            "api_key_env": "LLM_DEMO_TEST_API_KEY"
+        }))
+        .unwrap();
+
+        let request = Request::new("m".to_string());
+        gateway.do_request(&request).await.unwrap();
+        anthropic.do_request(&request).await.unwrap();
+        // Only headers matter, the empty answer is not a message.
+        assert!(anthropic_kind.do_request(&request).await.is_err());
+        let captured = server.await.unwrap();
+        std::fs::remove_file(&key_file).unwrap();
+
+        assert_eq!(captured[0].path, "/llm/v1/chat/completions");
+        assert_eq!(captured[0].headers["authorization"], "Bearer env-key");
+        assert_eq!(captured[0].headers["x-team"], "agents");
+        assert_eq!(captured[1].path, "/v1/chat/completions");
+        assert_eq!(captured[1].headers["x-api-key"], "file-key");
+        assert!(!captured[1].headers.contains_key("authorization"));
+        assert_eq!(captured[2].path, "/v1/messages");
+        assert_eq!(captured[2].headers["x-api-key"], "env-key");
+        assert!(!captured[2].headers.contains_key("authorization"));
+
+        let missing: Provider = serde_json::from_value(json!({
+            "name": "missing",
+            "base_url": "https://example.invalid",
+            "api_key_env": "LLM_DEMO_TEST_API_KEY_UNSET"
+        }))
+        .unwrap();
+        assert!(missing.do_request(&request).await.is_err());
+    }
+
+    fn mock_provider(addr: std::net::SocketAddr, max_retries: u32) -> Provider {
+        serde_json::from_value(json!({
+            "name": "mock",
+            "ip": addr.ip().to_string(),
+            "port": addr.port(),
+            "retry": { "max_retries": max_retries, "initial_backoff_ms": 1 }
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_errors() {
+        let (addr, server) = mock::serve(vec![
+            mock::Reply::status(503, "overloaded"),
+            mock::Reply::status(429, "slow down").header("Retry-After", "0"),
+            mock::Reply::status(200, "{}"),
+        ])
+        .await;
+        let request = Request::new("m".to_string());
+        let bytes = mock_provider(addr, 2).do_request(&request).await.unwrap();
+        assert_eq!(bytes, b"{}");
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_server_error() {
+        let provider = |addr: std::net::SocketAddr, backoff_ms: u64| -> Provider {
+            serde_json::from_value(json!({
+                "name": "mock",
+                "ip": addr.ip().to_string(),
+                "port": addr.port(),
+                "retry": { "initial_backoff_ms": backoff_ms, "max_backoff_ms": backoff_ms }
+            }))
+            .unwrap()
+        };
+        let request = Request::new("m".to_string());
+        let within = std::time::Duration::from_secs(5);
+
+        // Honored on 5xx rather than the backoff.
+        let (addr, server) = mock::serve(vec![
+            mock::Reply::status(503, "overloaded").header("Retry-After", "0"),
+            mock::Reply::status(200, "{}"),
+        ])
+        .await;
+        let retried = provider(addr, 60_000);
+        let done = tokio::time::timeout(within, retried.do_request(&request)).await;
+        assert!(done.unwrap().is_ok());
+        server.await.unwrap();
+
+        // Capped at the max backoff.
+        let (addr, server) = mock::serve(vec![
+            mock::Reply::status(503, "overloaded").header("Retry-After", "86400"),
+            mock::Reply::status(200, "{}"),
+        ])
+        .await;
+        let retried = provider(addr, 10);
+        let done = tokio::time::timeout(within, retried.do_request(&request)).await;
+        assert!(done.unwrap().is_ok());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up() {
+        let (addr, server) = mock::serve(vec![
+            mock::Reply::status(500, "boom"),
+            mock::Reply::status(500, "boom again"),
+        ])
+        .await;
+        let request = Request::new("m".to_string());
+        let error = mock_provider(addr, 1)
+            .do_request(&request)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, ProviderError::Status { status: 500, ref body, .. } if body == "boom again")
+        );
+        server.await.unwrap();
+
+        // Client errors are not retried.
+        let (addr, server) = mock::serve(vec![mock::Reply::status(400, "bad request")]).await;
+        let error = mock_provider(addr, 3)
+            .do_request(&request)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ProviderError::Status { status: 400, .. }));
+        assert!(!error.retryable());
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shared_client_read_timeout() {
+        // Accept and never answer.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            drop(socket);
+        });
+        let provider: Provider = serde_json::from_value(json!({
+            "name": "mock",
+            "ip": addr.ip().to_string(),
+            "port": addr.port(),
+            "retry": { "max_retries": 0 },
+            "http": { "read_timeout_ms": 100, "max_idle_connections": 4 }
+        }))
+        .unwrap();
+        let clone = provider.clone();
+
+        let request = Request::new("m".to_string());
+        let error = provider.do_request(&request).await.unwrap_err();
+        assert!(matches!(error, ProviderError::Transport(_)));
+        assert!(std::ptr::eq(
+            provider.client().unwrap(),
+            clone.client().unwrap()
+        ));
+        server.abort();
+
+        let bad_proxy: Provider = serde_json::from_value(json!({
+            "name": "bad",
+            "base_url": "http://localhost",
+            "http": { "proxy": "::not a url::" }
+        }))
+        .unwrap();
+        assert!(matches!(
+            bad_proxy.do_request(&request).await,
+            Err(ProviderError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_tolerant_response() {
+        let bytes = json!({
+            "choices": [
+                {
+                    "finish_reason": "content_filter",
+                    "message": { "role": "assistant", "content": null, "refusal": "Cannot help." }
+                },
+                {
+                    "index": 1,
+                    "finish_reason": "eos",
+                    "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call-2",
+                            "type": "function",
+                            "function": { "name": "shell", "arguments": "{}" }
+                        }]
+                    }
+                }
+            ],
+            "usage": null
+        })
+        .to_string();
+        let mut response = Response::from_u8(bytes.as_bytes()).unwrap();
+        assert_eq!(response.content(), "");
+        assert_eq!(response.refusal(), Some("Cannot help."));
+        assert_eq!(response.finish_reason(), Some(FinishReason::ContentFilter));
+        assert_eq!(response.choices[1].finish_reason, Some(FinishReason::Other));
+        assert_eq!(response.usage().total_tokens, 0);
+        // Calls of other choices are not taken, as only the first one joins the conversation.
+        assert!(response.tool_calls().is_empty());
+        assert!(response.message().tool_calls.is_none());
+
+        let empty = json!({ "id": "x", "choices": [] }).to_string();
+        assert!(Response::from_u8(empty.as_bytes()).is_err());
+        // Calls without id or type are taken as functions with ids of their own.
+        let bare = json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "tool_calls": [
+                        { "function": { "name": "shell", "arguments": "{}" } },
+                        { "id": null, "type": null, "function": { "name": "draft", "arguments": "{}" } }
+                    ]
+                }
+            }]
+        })
+        .to_string();
+        let mut response = Response::from_u8(bare.as_bytes()).unwrap();
+        let tool_calls = response.tool_calls();
+        assert_eq!(tool_calls[0].tool_calls_type, "function");
+        assert_eq!(tool_calls[1].tool_calls_type, "function");
+        assert!(!tool_calls[0].id.is_empty());
+        assert_ne!(tool_calls[0].id, tool_calls[1].id);
+        // The same response gets the same ids, so requests following it are cached alike.
+        let mut again = Response::from_u8(bare.as_bytes()).unwrap();
+        assert_eq!(again.tool_calls()[0].id, tool_calls[0].id);
+    }
+
+    #[test]
+    fn test_reasoning_extraction() {
+        let tagged = json!({
+            "choices": [{ "message": { "role": "assistant", "content": "<think>hm</think>pong" } }]
+        })
+        .to_string();
+        let response = Response::from_u8(tagged.as_bytes()).unwrap();
+        let exclude = Reasoning {
+            exclude_from_messages: true,
+            ..Reasoning::default()
+        };
+        assert_eq!(response.reasoning(&exclude).as_deref(), Some("hm"));
+        assert_eq!(response.message_with(&exclude).content, "pong");
+        assert_eq!(
+            response.message_with(&Reasoning::default()).content,
+            "<think>hm</think>pong"
+        );
+
+        let separate = json!({
+            "choices": [{
+                "message": { "role": "assistant", "content": "pong", "reasoning_content": "hm" }
+            }]
+        })
+        .to_string();
+        let response = Response::from_u8(separate.as_bytes()).unwrap();
+        assert_eq!(response.reasoning(&exclude).as_deref(), Some("hm"));
+        assert!(response.message_with(&exclude).reasoning_content.is_none());
+    }
+
+    #[test]
+    fn test_parse_text_tool_calls() {
+        let bytes = json!({
+            "choices": [{
+                "finish_reason": "stop",
+                "message": {
+                    "role": "assistant",
+                    "content": "<think>maybe <tool_call>{\"name\": \"draft\", \"arguments\": {}}</tool_call></think>Listing.<tool_call>{\"name\": \"shell\", \"arguments\": {\"executable\": \"ls\"}}</tool_call>"
+                }
+            }]
+        })
+        .to_string();
+        let mut response = Response::from_u8(bytes.as_bytes()).unwrap();
+        response.parse_text_tool_calls(TextToolCalls::Hermes, &Reasoning::default(), 1);
+
+        let tool_calls = response.tool_calls();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].function.name, "shell");
+        assert_eq!(response.finish_reason(), Some(FinishReason::ToolCalls));
+        assert_eq!(response.content(), "Listing.");
+
+        // Every turn gets ids of its own, or results would answer calls of earlier turns.
+        let mut again = Response::from_u8(bytes.as_bytes()).unwrap();
+        again.parse_text_tool_calls(TextToolCalls::Hermes, &Reasoning::default(), 2);
+        assert_ne!(again.tool_calls()[0].id, tool_calls[0].id);
+    }
+}
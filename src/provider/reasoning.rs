@@ -0,0 +1,76 @@
+// How a model marks its reasoning in content, and whether reasoning is sent back to it.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+pub struct Reasoning {
+    #[serde(default = "default_open_tag")]
+    pub open_tag: String,
+    #[serde(default = "default_close_tag")]
+    pub close_tag: String,
+    // Drop reasoning from messages put back to the conversation. It stays in history.
+    #[serde(default)]
+    pub exclude_from_messages: bool,
+}
+
+fn default_open_tag() -> String {
+    "<think>".to_string()
+}
+
+fn default_close_tag() -> String {
+    "</think>".to_string()
+}
+
+impl Default for Reasoning {
+    fn default() -> Self {
+        Reasoning {
+            open_tag: default_open_tag(),
+            close_tag: default_close_tag(),
+            exclude_from_messages: false,
+        }
+    }
+}
+
+impl Reasoning {
+    // Split content into reasoning and answer at the last close tag. The open tag is often
+    // left out by chat templates putting it into the prompt, so reasoning runs from the start
+    // of content if it is missing.
+    pub fn split(&self, content: &str) -> (Option<String>, String) {
+        let Some(close) = content.rfind(&self.close_tag) else {
+            return (None, content.trim().to_string());
+        };
+        let start = content[..close]
+            .find(&self.open_tag)
+            .map(|open| open + self.open_tag.len())
+            .unwrap_or(0);
+        let reasoning = content[start..close].trim().to_string();
+        let answer = content[close + self.close_tag.len()..].trim().to_string();
+        ((!reasoning.is_empty()).then_some(reasoning), answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_reasoning() {
+        let think = Reasoning::default();
+        assert_eq!(
+            think.split("<think>\nhm\n</think>\n\npong"),
+            (Some("hm".to_string()), "pong".to_string())
+        );
+        assert_eq!(
+            think.split("hm</think>pong"),
+            (Some("hm".to_string()), "pong".to_string())
+        );
+        assert_eq!(think.split(" pong "), (None, "pong".to_string()));
+
+        let custom = Reasoning {
+            open_tag: "<|begin_of_thought|>".to_string(),
+            close_tag: "<|end_of_thought|>".to_string(),
+            exclude_from_messages: true,
+        };
+        assert_eq!(
+            custom.split("<|begin_of_thought|>hm<|end_of_thought|>pong"),
+            (Some("hm".to_string()), "pong".to_string())
+        );
+    }
+}
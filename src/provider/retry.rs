@@ -0,0 +1,102 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+
+// Retry budget of a service. Connection errors, 429 and 5xx are retried.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct RetryPolicy {
+    // Retries after the first attempt. 0 to fail at once.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    // Backoff before the first retry, doubled on each one.
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_backoff_ms() -> u64 {
+    30_000
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: default_max_retries(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Delay before retry `attempt` (from 0). Jittered between half and the whole of the
+    // exponential backoff, so tasks failing together do not retry together.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(1u64 << attempt.min(32))
+            .min(self.max_backoff_ms);
+        let half = backoff / 2;
+        Duration::from_millis(half + jitter() % (backoff - half + 1))
+    }
+
+    // Delay before retry `attempt`, as the service asked if it did. Capped at the max backoff,
+    // so a service asking for hours does not stall the task that long.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(asked) => asked.min(Duration::from_millis(self.max_backoff_ms)),
+            None => self.backoff(attempt),
+        }
+    }
+}
+
+// Random enough for spreading retries, without pulling in a rand crate.
+fn jitter() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+// Delay asked by `Retry-After` in seconds. HTTP dates are not honored.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_bounds() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+        };
+        for _ in 0..20 {
+            let first = policy.backoff(0);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let capped = policy.backoff(10);
+            assert!(capped >= Duration::from_millis(500) && capped <= Duration::from_millis(1000));
+        }
+        let asked = Some(Duration::from_secs(86400));
+        assert_eq!(policy.delay(0, asked), Duration::from_millis(1000));
+        assert_eq!(policy.delay(0, Some(Duration::ZERO)), Duration::ZERO);
+    }
+}
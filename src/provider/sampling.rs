@@ -0,0 +1,103 @@
+use serde_json::json;
+
+// Sampling parameters sent with a request. Unset ones are left to the service.
+// Named as in OpenAI chat completions, and translated by backends speaking other APIs.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default, PartialEq, Debug)]
+pub struct Sampling {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    // Fixed seed for reproducible runs, where the service supports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    // `"auto"`, `"none"`, `"required"`, or `{"type": "function", "function": {"name": ...}}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    // `{"type": "json_object"}` or `{"type": "json_schema", "json_schema": {...}}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<serde_json::Value>,
+}
+
+impl Sampling {
+    // Parameters of self overridden by those set in `overrides`.
+    pub fn merge(&self, overrides: &Sampling) -> Sampling {
+        Sampling {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            stop: overrides.stop.clone().or_else(|| self.stop.clone()),
+            seed: overrides.seed.or(self.seed),
+            tool_choice: overrides
+                .tool_choice
+                .clone()
+                .or_else(|| self.tool_choice.clone()),
+            parallel_tool_calls: overrides.parallel_tool_calls.or(self.parallel_tool_calls),
+            response_format: overrides
+                .response_format
+                .clone()
+                .or_else(|| self.response_format.clone()),
+        }
+    }
+
+    // Parameters for a request offering no tools. Servers reject tool parameters without them.
+    pub fn without_tools(&self) -> Sampling {
+        Sampling {
+            tool_choice: None,
+            parallel_tool_calls: None,
+            ..self.clone()
+        }
+    }
+
+    // Set parameters as fields of a JSON object, as OpenAI takes them.
+    pub fn apply(&self, body: &mut serde_json::Value) {
+        if let serde_json::Value::Object(params) = json!(self) {
+            for (key, value) in params {
+                body[key] = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_and_apply() {
+        let model: Sampling =
+            serde_json::from_value(json!({ "temperature": 0.6, "seed": 1, "stop": ["</s>"] }))
+                .unwrap();
+        let task: Sampling =
+            serde_json::from_value(json!({ "temperature": 0.0, "max_tokens": 512 })).unwrap();
+
+        let mut body = json!({ "model": "m" });
+        model.merge(&task).apply(&mut body);
+
+        assert_eq!(
+            body,
+            json!({ "model": "m", "temperature": 0.0, "max_tokens": 512, "seed": 1, "stop": ["</s>"] })
+        );
+    }
+
+    #[test]
+    fn test_without_tools() {
+        let model: Sampling = serde_json::from_value(json!({
+            "temperature": 0.2,
+            "tool_choice": "required",
+            "parallel_tool_calls": false
+        }))
+        .unwrap();
+
+        let mut body = json!({});
+        model.without_tools().apply(&mut body);
+
+        assert_eq!(body, json!({ "temperature": 0.2 }));
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+
+use futures::{Stream, StreamExt};
+
+use super::{Choice, FinishReason, Message, Response, Roles, ToolCall, ToolCallFunction, Usage};
+use crate::utils::ProviderError;
+
+// Events of a streamed chat completion. Dropping the stream cancels the request.
+pub enum StreamEvent {
+    // Piece of content as it is generated, thinking part included.
+    Delta(String),
+    // Piece of reasoning returned apart from content.
+    ReasoningDelta(String),
+    // Fragment of a tool call. Id and name come with the first fragment of each call.
+    ToolCallDelta {
+        index: usize,
+        name: Option<String>,
+        arguments: String,
+    },
+    // Whole response assembled from all chunks, once the stream ends.
+    Done(Response),
+}
+
+// Turn the body of `text/event-stream` into events.
+pub fn events<S, B, E>(body: S) -> impl Stream<Item = Result<StreamEvent, ProviderError>>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: Display,
+{
+    let state = State {
+        body,
+        parser: SseParser::default(),
+        accumulator: Some(Accumulator::default()),
+        pending: VecDeque::new(),
+        done: false,
+    };
+    futures::stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(event) = state.pending.pop_front() {
+                return Some((Ok(event), state));
+            }
+            // Accumulator is taken once the stream ends or fails.
+            state.accumulator.as_ref()?;
+            if state.done {
+                let response = state.accumulator.take()?.finish();
+                return Some((Ok(StreamEvent::Done(response)), state));
+            }
+            match state.body.next().await {
+                Some(Ok(bytes)) => {
+                    for data in state.parser.push(bytes.as_ref()) {
+                        if data.trim() == "[DONE]" {
+                            state.done = true;
+                            break;
+                        }
+                        let accumulator = state.accumulator.as_mut()?;
+                        match accumulator.push(&data) {
+                            Ok(events) => state.pending.extend(events),
+                            Err(e) => {
+                                state.accumulator = None;
+                                return Some((Err(e), state));
+                            }
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    state.accumulator = None;
+                    return Some((Err(ProviderError::Transport(e.to_string())), state));
+                }
+                // Some servers close the stream without [DONE].
+                None => state.done = true,
+            }
+        }
+    })
+}
+
+struct State<S> {
+    body: S,
+    parser: SseParser,
+    accumulator: Option<Accumulator>,
+    pending: VecDeque<StreamEvent>,
+    done: bool,
+}
+
+// Split server sent events into their data payloads. Events may be cut anywhere by chunks.
+#[derive(Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+}
+
+impl SseParser {
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend(bytes.iter().filter(|b| **b != b'\r'));
+        let mut payloads = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let event: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let data: Vec<_> = String::from_utf8_lossy(&event)
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data).to_string())
+                .collect();
+            if !data.is_empty() {
+                payloads.push(data.join("\n"));
+            }
+        }
+        payloads
+    }
+}
+
+// Chunk of `chat.completion.chunk`.
+#[derive(serde::Deserialize)]
+struct Chunk {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    created: u64,
+    #[serde(default)]
+    model: String,
+    #[serde(default)]
+    system_fingerprint: Option<String>,
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(serde::Deserialize)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: ChunkDelta,
+    finish_reason: Option<FinishReason>,
+}
+
+#[derive(serde::Deserialize, Default)]
+struct ChunkDelta {
+    content: Option<String>,
+    reasoning_content: Option<String>,
+    tool_calls: Option<Vec<ToolCallChunk>>,
+}
+
+#[derive(serde::Deserialize)]
+struct ToolCallChunk {
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionChunk>,
+}
+
+#[derive(serde::Deserialize)]
+struct FunctionChunk {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+// Assemble chunks of the first choice into a whole response.
+#[derive(Default)]
+struct Accumulator {
+    id: String,
+    created: u64,
+    model: String,
+    system_fingerprint: Option<String>,
+    content: String,
+    reasoning_content: String,
+    tool_calls: Vec<ToolCall>,
+    finish_reason: Option<FinishReason>,
+    usage: Option<Usage>,
+}
+
+impl Accumulator {
+    fn push(&mut self, data: &str) -> Result<Vec<StreamEvent>, ProviderError> {
+        let chunk: Chunk = serde_json::from_str(data).map_err(|e| {
+            ProviderError::Decode(format!("Unmarshal stream chunk error: {}: {}", e, data))
+        })?;
+        if self.id.is_empty() {
+            self.id = chunk.id;
+            self.created = chunk.created;
+            self.model = chunk.model;
+        }
+        if chunk.system_fingerprint.is_some() {
+            self.system_fingerprint = chunk.system_fingerprint;
+        }
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage;
+        }
+        let mut events = Vec::new();
+        let Some(choice) = chunk.choices.into_iter().next() else {
+            return Ok(events);
+        };
+        if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+            self.content.push_str(&content);
+            events.push(StreamEvent::Delta(content));
+        }
+        if let Some(reasoning) = choice.delta.reasoning_content.filter(|r| !r.is_empty()) {
+            self.reasoning_content.push_str(&reasoning);
+            events.push(StreamEvent::ReasoningDelta(reasoning));
+        }
+        for fragment in choice.delta.tool_calls.into_iter().flatten() {
+            while self.tool_calls.len() <= fragment.index {
+                self.tool_calls.push(ToolCall {
+                    id: String::new(),
+                    tool_calls_type: "function".to_string(),
+                    function: ToolCallFunction {
+                        name: String::new(),
+                        arguments: String::new(),
+                    },
+                });
+            }
+            let tool_call = &mut self.tool_calls[fragment.index];
+            if let Some(id) = fragment.id {
+                tool_call.id = id;
+            }
+            let (name, arguments) = fragment
+                .function
+                .map(|f| (f.name, f.arguments.unwrap_or_default()))
+                .unwrap_or_default();
+            if let Some(name) = &name {
+                tool_call.function.name.push_str(name);
+            }
+            tool_call.function.arguments.push_str(&arguments);
+            events.push(StreamEvent::ToolCallDelta {
+                index: fragment.index,
+                name,
+                arguments,
+            });
+        }
+        if choice.finish_reason.is_some() {
+            self.finish_reason = choice.finish_reason;
+        }
+        Ok(events)
+    }
+
+    fn finish(self) -> Response {
+        Response {
+            id: self.id,
+            object: "chat.completion".to_string(),
+            created: self.created,
+            model: self.model,
+            choices: vec![Choice {
+                index: 0,
+                finish_reason: self.finish_reason,
+                message: Message {
+                    role: Roles::Assistant,
+                    content: self.content,
+                    tool_calls: (!self.tool_calls.is_empty()).then_some(self.tool_calls),
+                    tool_call_id: None,
+                    refusal: None,
+                    reasoning_content: (!self.reasoning_content.is_empty())
+                        .then_some(self.reasoning_content),
+                },
+            }],
+            usage: self.usage.unwrap_or_default(),
+            system_fingerprint: self.system_fingerprint,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stream_events() {
+        // Events are cut at arbitrary places by chunks.
+        let body = concat!(
+            "data: {\"id\":\"c1\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"<think>hm\"},\"finish_reason\":null}]}\r\n\r\n",
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"</think>\"},\"finish_reason\":null}]}\n\n",
+            ": keep-alive\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call-1\",\"type\":\"function\",\"function\":{\"name\":\"shell\",\"arguments\":\"\"}}]},\"finish_reason\":null}]}\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"executable\\\":\"}}]},\"finish_reason\":null}]}\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"ls\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[],\"usage\":{\"prompt_tokens\":3,\"completion_tokens\":4,\"total_tokens\":7}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let chunks: Vec<Result<Vec<u8>, String>> =
+            body.as_bytes().chunks(17).map(|c| Ok(c.to_vec())).collect();
+
+        let events: Vec<_> = events(futures::stream::iter(chunks)).collect().await;
+        let events: Vec<_> = events.into_iter().map(|e| e.unwrap()).collect();
+
+        let deltas: String = events
+            .iter()
+            .filter_map(|e| match e {
+                StreamEvent::Delta(d) => Some(d.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(deltas, "<think>hm</think>");
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| matches!(e, StreamEvent::ToolCallDelta { .. }))
+                .count(),
+            3
+        );
+        let Some(StreamEvent::Done(mut response)) = events.into_iter().last() else {
+            panic!("stream should end with Done");
+        };
+        assert_eq!(response.id, "c1");
+        assert_eq!(response.finish_reason(), Some(FinishReason::ToolCalls));
+        assert_eq!(response.usage().total_tokens, 7);
+        let tool_calls = response.tool_calls();
+        assert_eq!(tool_calls[0].id, "call-1");
+        assert_eq!(tool_calls[0].function.name, "shell");
+        assert_eq!(tool_calls[0].function.arguments, "{\"executable\":\"ls\"}");
+    }
+
+    #[tokio::test]
+    async fn test_stream_bad_chunk() {
+        let chunks: Vec<Result<&[u8], String>> = vec![Ok(b"data: {oops\n\n")];
+        let events: Vec<_> = events(futures::stream::iter(chunks)).collect().await;
+        assert_eq!(events.len(), 1);
+        assert!(events[0].is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use serde_json::json;
+
+use super::{Message, Roles, ToolCallFunction};
+use crate::tool::Tool;
+
+// How tools are offered to a model.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolMode {
+    // In the `tools` field of requests.
+    #[default]
+    Native,
+    // Rendered into the system prompt, for servers rejecting or ignoring the field. Calls and
+    // results are exchanged as text in the Hermes convention.
+    Prompt,
+}
+
+// How to find tool calls written as text in content, for models that do not fill the
+// structured `tool_calls` field.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TextToolCalls {
+    // Only structured tool calls are taken.
+    #[default]
+    Off,
+    // `<tool_call>{"name": ..., "arguments": {...}}</tool_call>`, as Hermes and Qwen do.
+    Hermes,
+    // Call objects in fenced code blocks, e.g. ```json {"name": ..., "arguments": {...}} ```.
+    Fenced,
+    // Any of the above, or content being a call object by itself.
+    Auto,
+}
+
+// Tool calls found, and content left without them.
+type Extracted = (Vec<ToolCallFunction>, String);
+
+const HERMES_OPEN: &str = "<tool_call>";
+const HERMES_CLOSE: &str = "</tool_call>";
+const FENCE: &str = "```";
+
+impl TextToolCalls {
+    // Tool calls found in content, and content with them cut out. None if there is none.
+    pub fn extract(&self, content: &str) -> Option<Extracted> {
+        let found = |parse: fn(&str) -> Option<Extracted>| {
+            parse(content).filter(|(calls, _)| !calls.is_empty())
+        };
+        match self {
+            TextToolCalls::Off => None,
+            TextToolCalls::Hermes => found(hermes),
+            TextToolCalls::Fenced => found(fenced),
+            TextToolCalls::Auto => found(hermes)
+                .or_else(|| found(fenced))
+                .or_else(|| found(bare)),
+        }
+    }
+}
+
+// System prompt describing tools and how to call them, in place of the `tools` field.
+pub fn tools_prompt(tools: &[Box<dyn Tool>]) -> String {
+    let docs: Vec<_> = tools.iter().map(|t| t.tooldoc().to_string()).collect();
+    format!(
+        "You may call the tools below, described by JSON schemas:\n<tools>\n{}\n</tools>\n\nTo call a tool, write a block in exactly this format, one block per call:\n{}\n{{\"name\": \"<function name>\", \"arguments\": <arguments as a JSON object>}}\n{}\nResults come back in <tool_response></tool_response> blocks. Never write results yourself.",
+        docs.join("\n"),
+        HERMES_OPEN,
+        HERMES_CLOSE
+    )
+}
+
+// Conversation with tool calls and results turned into text, for servers not knowing them.
+pub fn render_messages(messages: &[Message]) -> Vec<Message> {
+    messages
+        .iter()
+        .map(|message| match message.role {
+            Roles::Assistant if message.tool_calls.is_some() => {
+                let mut content = message.content.clone();
+                for tool_call in message.tool_calls.iter().flatten() {
+                    let arguments: serde_json::Value =
+                        serde_json::from_str(&tool_call.function.arguments)
+                            .unwrap_or_else(|_| json!(tool_call.function.arguments));
+                    content.push_str(&format!(
+                        "\n{}\n{}\n{}",
+                        HERMES_OPEN,
+                        json!({ "name": tool_call.function.name, "arguments": arguments }),
+                        HERMES_CLOSE
+                    ));
+                }
+                Message::new(Roles::Assistant, content.trim().to_string())
+            }
+            Roles::Tool => Message::new(
+                Roles::User,
+                format!("<tool_response>\n{}\n</tool_response>", message.content),
+            ),
+            _ => message.clone(),
+        })
+        .collect()
+}
+
+fn hermes(content: &str) -> Option<Extracted> {
+    let mut calls = Vec::new();
+    let mut rest = String::new();
+    let mut remaining = content;
+    while let Some(start) = remaining.find(HERMES_OPEN) {
+        rest.push_str(&remaining[..start]);
+        let body = &remaining[start + HERMES_OPEN.len()..];
+        // Small models often stop before closing the last call.
+        let (inner, after) = match body.find(HERMES_CLOSE) {
+            Some(end) => (&body[..end], &body[end + HERMES_CLOSE.len()..]),
+            None => (body, ""),
+        };
+        calls.extend(parse_calls(inner)?);
+        remaining = after;
+    }
+    rest.push_str(remaining);
+    Some((calls, rest.trim().to_string()))
+}
+
+fn fenced(content: &str) -> Option<Extracted> {
+    let mut calls = Vec::new();
+    let mut rest = String::new();
+    let mut remaining = content;
+    while let Some(start) = remaining.find(FENCE) {
+        let body = &remaining[start + FENCE.len()..];
+        let Some(end) = body.find(FENCE) else {
+            break;
+        };
+        // Skip the language tag of the block.
+        let inner = body[..end]
+            .trim_start_matches(|c: char| c.is_ascii_alphanumeric())
+            .trim();
+        match parse_calls(inner) {
+            Some(found) => {
+                rest.push_str(&remaining[..start]);
+                calls.extend(found);
+            }
+            // Code blocks other than calls stay in content.
+            None => rest.push_str(&remaining[..start + FENCE.len() * 2 + end]),
+        }
+        remaining = &body[end + FENCE.len()..];
+    }
+    rest.push_str(remaining);
+    Some((calls, rest.trim().to_string()))
+}
+
+fn bare(content: &str) -> Option<Extracted> {
+    parse_calls(content.trim()).map(|calls| (calls, String::new()))
+}
+
+// A call object or an array of them. None unless every one looks like a call.
+fn parse_calls(text: &str) -> Option<Vec<ToolCallFunction>> {
+    let value: serde_json::Value = serde_json::from_str(text.trim()).ok()?;
+    let values = match value {
+        serde_json::Value::Array(values) => values,
+        value => vec![value],
+    };
+    values.iter().map(parse_call).collect()
+}
+
+fn parse_call(value: &serde_json::Value) -> Option<ToolCallFunction> {
+    // Some models nest the call in `function` as OpenAI does.
+    let value = value.get("function").unwrap_or(value);
+    let name = value.get("name")?.as_str()?.to_string();
+    let arguments = value.get("arguments").or_else(|| value.get("parameters"))?;
+    Some(ToolCallFunction {
+        name,
+        arguments: match arguments {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_text_tool_calls() {
+        let hermes = "Let me look.\n<tool_call>\n{\"name\": \"shell\", \"arguments\": {\"executable\": \"ls\"}}\n</tool_call>\n<tool_call>{\"name\": \"task_ends\", \"arguments\": \"{}\"}";
+        let (calls, rest) = TextToolCalls::Hermes.extract(hermes).unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].name, "shell");
+        assert_eq!(calls[0].arguments, "{\"executable\":\"ls\"}");
+        assert_eq!(calls[1].arguments, "{}");
+        assert_eq!(rest, "Let me look.");
+
+        let fenced = "Run this:\n```bash\nls\n```\n```json\n{\"name\": \"shell\", \"parameters\": {\"executable\": \"ls\"}}\n```";
+        let (calls, rest) = TextToolCalls::Fenced.extract(fenced).unwrap();
+        assert_eq!(calls[0].name, "shell");
+        assert_eq!(rest, "Run this:\n```bash\nls\n```");
+
+        let bare = "{\"function\": {\"name\": \"shell\", \"arguments\": {}}}";
+        assert!(TextToolCalls::Fenced.extract(bare).is_none());
+        assert_eq!(
+            TextToolCalls::Auto.extract(bare).unwrap().0[0].name,
+            "shell"
+        );
+
+        assert!(TextToolCalls::Auto.extract("Just an answer.").is_none());
+        assert!(TextToolCalls::Off.extract(hermes).is_none());
+    }
+
+    #[test]
+    fn test_render_messages_round_trip() {
+        let messages: Vec<Message> = serde_json::from_value(json!([
+            { "role": "user", "content": "list files" },
+            {
+                "role": "assistant",
+                "content": "Listing.",
+                "tool_calls": [{
+                    "id": "call-1",
+                    "type": "function",
+                    "function": { "name": "shell", "arguments": "{\"executable\":\"ls\"}" }
+                }]
+            },
+            { "role": "tool", "content": "Cargo.toml", "tool_call_id": "call-1" }
+        ]))
+        .unwrap();
+
+        let rendered = render_messages(&messages);
+        assert!(rendered[1].tool_calls.is_none());
+        assert!(matches!(rendered[2].role, Roles::User));
+        assert!(rendered[2].content.contains("Cargo.toml"));
+
+        // The model answers in the convention it is shown.
+        let (calls, rest) = TextToolCalls::Hermes.extract(&rendered[1].content).unwrap();
+        assert_eq!(rest, "Listing.");
+        assert_eq!(calls[0].name, "shell");
+        assert_eq!(calls[0].arguments, "{\"executable\":\"ls\"}");
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::{model::TokenUsage, provider::Message, task::Task, utils::CheckpointError};
+
+use super::RuntimeTaskStatus;
+
+// Full state of a task between iterations, enough to resume it after a restart.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Checkpoint {
+    pub task: Task,
+    pub messages: Vec<Message>,
+    // Tool snapshots by tool name.
+    pub tools: HashMap<String, serde_json::Value>,
+    pub iterations: usize,
+    pub tool_retries: usize,
+    pub status: RuntimeTaskStatus,
+    #[serde(default)]
+    pub usage: TokenUsage,
+}
+
+impl Checkpoint {
+    // Checkpoint is kept beside the transcript of the task.
+    pub fn path<P>(dir: P, task_name: &str) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        dir.as_ref().join(format!("{}.checkpoint.json", task_name))
+    }
+
+    // Overwrite the checkpoint at once, so a crash never leaves a partial file behind.
+    pub fn save<P>(&self, path: P) -> Result<(), CheckpointError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| {
+                CheckpointError::new(format!("Create directory {}: {}", dir.display(), e))
+            })?;
+        }
+        let content = serde_json::to_vec(self)
+            .map_err(|e| CheckpointError::new(format!("Marshal checkpoint: {}", e)))?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, content)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| CheckpointError::new(format!("Write {}: {}", path.display(), e)))
+    }
+
+    pub fn load<P>(path: P) -> Result<Self, CheckpointError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let content = std::fs::read(path)
+            .map_err(|e| CheckpointError::new(format!("Read {}: {}", path.display(), e)))?;
+        serde_json::from_slice(&content)
+            .map_err(|e| CheckpointError::new(format!("Unmarshal {}: {}", path.display(), e)))
+    }
+
+    // All checkpoints kept in the directory.
+    pub fn list<P>(dir: P) -> Result<Vec<PathBuf>, CheckpointError>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .map_err(|e| CheckpointError::new(format!("Read {}: {}", dir.display(), e)))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.ends_with(".checkpoint.json"))
+            })
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+}
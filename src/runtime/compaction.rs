@@ -0,0 +1,76 @@
+use crate::provider::{Message, Roles};
+
+// Compact once the prompt of last iteration takes this share of the context window.
+pub const COMPACT_RATIO: f64 = 0.8;
+
+// Latest assistant turns kept verbatim. Older turns are summarized.
+pub const KEEP_TURNS: usize = 2;
+
+pub const SUMMARY_PROMPT: &str = "You are compressing the working memory of an agent. Summarize the conversation below into a compact report of what has been done, what has been found about the environment, and what remains to do. Keep exact names, paths, commands and error messages that matter. Answer with the summary only.";
+
+// Index where the kept tail of the conversation begins, or None if nothing is worth compacting.
+// The first message holds the original target and is always kept. The tail starts at an
+// assistant message, so tool results are never separated from the tool calls they answer.
+pub fn split_point(messages: &[Message], keep_turns: usize) -> Option<usize> {
+    let assistants: Vec<_> = messages
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, m)| matches!(m.role, Roles::Assistant))
+        .map(|(i, _)| i)
+        .collect();
+    if assistants.len() <= keep_turns {
+        return None;
+    }
+    let split = assistants[assistants.len() - keep_turns.max(1)];
+    (split > 1).then_some(split)
+}
+
+// Plain text rendering of messages for the summarizer, so tool call pairing does not matter.
+pub fn render(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .map(|m| {
+            let mut line = format!("[{:?}] {}", m.role, m.content);
+            for tool_call in m.tool_calls.iter().flatten() {
+                line.push_str(&format!(
+                    "\n  call {}({})",
+                    tool_call.function.name, tool_call.function.arguments
+                ));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str) -> Message {
+        Message::new(Roles::from(role), role.to_string())
+    }
+
+    #[test]
+    fn test_split_point_keeps_target_and_turns() {
+        let messages: Vec<_> = [
+            "user",
+            "assistant",
+            "tool",
+            "assistant",
+            "tool",
+            "tool",
+            "assistant",
+            "tool",
+        ]
+        .into_iter()
+        .map(message)
+        .collect();
+
+        assert_eq!(split_point(&messages, 2), Some(3));
+        assert_eq!(split_point(&messages, 1), Some(6));
+        assert_eq!(split_point(&messages, 3), None);
+        assert_eq!(split_point(&messages[..3], 0), None);
+    }
+}
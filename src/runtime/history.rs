@@ -0,0 +1,278 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    provider::{
+        reasoning::Reasoning, text_tools::TextToolCalls, Message, Response, Roles, ToolCall,
+    },
+    utils::TranscriptError,
+};
+
+// One exchange of the task, either with the model or with a tool.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct RuntimeHistory {
+    pub time: SystemTime,
+    // Time taken from request to response.
+    pub elapsed: Duration,
+    pub kind: HistoryKind,
+    // Request body for model and the tool call for tool.
+    pub request: String,
+    // Response body for model and the result fed back for tool.
+    pub response: String,
+    // Reasoning of the model extracted from response, for audit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryKind {
+    Model,
+    Tool,
+    // Summarization of older turns on compaction.
+    Summary,
+}
+
+// Append-only JSONL file of RuntimeHistory, one per task.
+pub struct Transcript {
+    path: PathBuf,
+}
+
+impl Transcript {
+    pub fn new<P>(dir: P, task_name: &str) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Transcript {
+            path: dir.as_ref().join(format!("{}.jsonl", task_name)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Move the transcript of a previous run aside, to `{task_name}.{unix millis}.jsonl`, so
+    // a new run starts from an empty one. Returns where it went, if there was one.
+    pub fn rotate(&self) -> Result<Option<PathBuf>, TranscriptError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let millis = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let rotated = self.path.with_extension(format!("{}.jsonl", millis));
+        std::fs::rename(&self.path, &rotated)
+            .map_err(|e| TranscriptError::new(format!("Rotate {}: {}", self.path.display(), e)))?;
+        Ok(Some(rotated))
+    }
+
+    pub fn append(&self, history: &RuntimeHistory) -> Result<(), TranscriptError> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| {
+                TranscriptError::new(format!("Create directory {}: {}", dir.display(), e))
+            })?;
+        }
+        let mut line = serde_json::to_string(history)
+            .map_err(|e| TranscriptError::new(format!("Marshal history: {}", e)))?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| f.write_all(line.as_bytes()))
+            .map_err(|e| TranscriptError::new(format!("Write {}: {}", self.path.display(), e)))
+    }
+
+    // Read all records of the transcript in order.
+    pub fn load<P>(path: P) -> Result<Vec<RuntimeHistory>, TranscriptError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|e| TranscriptError::new(format!("Open {}: {}", path.display(), e)))?;
+        let mut history = Vec::new();
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line =
+                line.map_err(|e| TranscriptError::new(format!("Read {}: {}", path.display(), e)))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            history.push(serde_json::from_str(&line).map_err(|e| {
+                TranscriptError::new(format!("Line {} of {}: {}", n + 1, path.display(), e))
+            })?);
+        }
+        Ok(history)
+    }
+}
+
+// Reconstruct the conversation from history. The last model request holds the whole
+// conversation by then, followed by the answer and the tool results after it.
+pub fn conversation(history: &[RuntimeHistory]) -> Result<Vec<Message>, TranscriptError> {
+    let Some(last) = history.iter().rposition(|h| h.kind == HistoryKind::Model) else {
+        return Ok(Vec::new());
+    };
+    let request: serde_json::Value = serde_json::from_str(&history[last].request)
+        .map_err(|e| TranscriptError::new(format!("Unmarshal model request: {}", e)))?;
+    let mut messages: Vec<Message> = serde_json::from_value(request["messages"].clone())
+        .map_err(|e| TranscriptError::new(format!("Unmarshal request messages: {}", e)))?;
+    let mut tool_calls = Vec::new();
+    for h in history[last + 1..]
+        .iter()
+        .filter(|h| h.kind == HistoryKind::Tool)
+    {
+        let tool_call: ToolCall = serde_json::from_str(&h.request)
+            .map_err(|e| TranscriptError::new(format!("Unmarshal tool call: {}", e)))?;
+        tool_calls.push((tool_call, h.response.clone()));
+    }
+    // A run often ends on the answer it failed to parse. It is shown as is.
+    let answer = match Response::from_u8(history[last].response.as_bytes()) {
+        Ok(mut response) => {
+            // Calls written as text were parsed by the runtime after recording. Tool records
+            // hold them with the ids they were given.
+            if response.tool_calls().is_empty() && !tool_calls.is_empty() {
+                response.parse_text_tool_calls(TextToolCalls::Auto, &Reasoning::default(), 0);
+                Message {
+                    tool_calls: Some(tool_calls.iter().map(|(c, _)| c.clone()).collect()),
+                    ..response.message()
+                }
+            } else {
+                response.message()
+            }
+        }
+        Err(e) => {
+            log::warn!("Show the last answer unparsed: {}", e);
+            Message::new(Roles::Assistant, history[last].response.clone())
+        }
+    };
+    messages.push(answer);
+    for (tool_call, result) in tool_calls {
+        messages.push(Message {
+            tool_call_id: Some(tool_call.id),
+            ..Message::new(Roles::Tool, result)
+        });
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcript_round_trip() {
+        let dir = std::env::temp_dir().join(format!("transcript-{}", std::process::id()));
+        let transcript = Transcript::new(&dir, "round_trip");
+        let request = serde_json::json!({
+            "model": "mock",
+            "messages": [{ "role": "user", "content": "list files" }],
+            "tools": [],
+        });
+        let response = serde_json::json!({
+            "id": "1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "mock",
+            "choices": [{
+                "index": 0,
+                "finish_reason": "tool_calls",
+                "message": {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [{
+                        "id": "call-1",
+                        "type": "function",
+                        "function": { "name": "shell", "arguments": "{}" }
+                    }]
+                }
+            }],
+            "usage": { "prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2 },
+            "system_fingerprint": "mock"
+        });
+        let tool_call =
+            serde_json::to_string(&response["choices"][0]["message"]["tool_calls"][0]).unwrap();
+        for (kind, request, response) in [
+            (
+                HistoryKind::Model,
+                request.to_string(),
+                response.to_string(),
+            ),
+            (
+                HistoryKind::Tool,
+                tool_call,
+                "{\"stdout\":\"a\"}".to_string(),
+            ),
+        ] {
+            transcript
+                .append(&RuntimeHistory {
+                    time: SystemTime::now(),
+                    elapsed: Duration::from_millis(5),
+                    kind,
+                    request,
+                    response,
+                    reasoning: None,
+                })
+                .unwrap();
+        }
+
+        let history = Transcript::load(dir.join("round_trip.jsonl")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].elapsed, Duration::from_millis(5));
+
+        let messages = conversation(&history).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].content, "list files");
+        assert_eq!(messages[1].tool_calls.as_ref().unwrap()[0].id, "call-1");
+        assert_eq!(messages[2].tool_call_id.as_deref(), Some("call-1"));
+        assert_eq!(messages[2].content, "{\"stdout\":\"a\"}");
+    }
+
+    #[test]
+    fn test_conversation_of_bad_runs() {
+        let record = |kind, request: &str, response: &str| RuntimeHistory {
+            time: SystemTime::now(),
+            elapsed: Duration::ZERO,
+            kind,
+            request: request.to_string(),
+            response: response.to_string(),
+            reasoning: None,
+        };
+        let request =
+            r#"{"model": "mock", "messages": [{ "role": "user", "content": "list files" }]}"#;
+
+        // Calls parsed from text show up with the ids their results answer.
+        let answer = serde_json::json!({
+            "choices": [{ "message": {
+                "role": "assistant",
+                "content": "Listing.<tool_call>{\"name\": \"shell\", \"arguments\": {}}</tool_call>"
+            } }]
+        });
+        let tool_call = r#"{"id": "text_call_1_0", "type": "function", "function": { "name": "shell", "arguments": "{}" }}"#;
+        let history = [
+            record(HistoryKind::Model, request, &answer.to_string()),
+            record(HistoryKind::Tool, tool_call, "Cargo.toml"),
+        ];
+        let messages = conversation(&history).unwrap();
+        assert_eq!(messages[1].content, "Listing.");
+        assert_eq!(
+            messages[1].tool_calls.as_ref().unwrap()[0].id,
+            "text_call_1_0"
+        );
+        assert_eq!(messages[2].tool_call_id.as_deref(), Some("text_call_1_0"));
+
+        // An answer that could not be parsed is kept as text.
+        let history = [record(
+            HistoryKind::Model,
+            request,
+            "<html>Bad gateway</html>",
+        )];
+        let messages = conversation(&history).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].content, "<html>Bad gateway</html>");
+    }
+}
@@ -0,0 +1,1258 @@
+use futures::{stream::FuturesUnordered, StreamExt};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{
+    config::Config,
+    model::{Model, TokenUsage},
+    provider::{
+        sampling::Sampling,
+        stream::StreamEvent,
+        text_tools::{self, ToolMode},
+        FinishReason, Message, Request, Response, Roles, ToolCall,
+    },
+    task::Task,
+    tool::{available_tools, Tool},
+    utils::{
+        CheckpointError, ModelNotRegistered, ProviderError, ToolCallingError, ToolNotRegistered,
+        TranscriptError,
+    },
+};
+
+mod checkpoint;
+mod compaction;
+pub mod history;
+mod scheduler;
+
+use checkpoint::Checkpoint;
+use history::{HistoryKind, RuntimeHistory, Transcript};
+use scheduler::TaskQueue;
+
+pub struct Runtime {
+    config: Config,
+    // Registry of models by name. Shared with tasks running on them.
+    models: HashMap<String, Arc<Model>>,
+    // Models served by each service queried at init.
+    served: HashMap<String, Vec<String>>,
+    tools: Vec<Box<dyn Tool>>,
+    // Tasks waiting to be stepped, either not started or between iterations.
+    queue: TaskQueue<RuntimeTask>,
+    // Ended tasks.
+    tasks: Vec<RuntimeTask>,
+}
+
+impl Runtime {
+    pub async fn init(config: Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut models: HashMap<String, Arc<Model>> = config
+            .to_models()?
+            .into_iter()
+            .map(|m| (m.name().to_string(), Arc::new(m)))
+            .collect();
+        let served = Self::discover(&config).await;
+        // Configured models should exist on services that could be queried. Aliases keep
+        // working on the backends left, and tasks on models served by none are refused.
+        for model in models.values() {
+            for backend in model.backends() {
+                if let Some(names) = served.get(&backend.provider.name) {
+                    if !is_served(names, &backend.model) {
+                        log::warn!(
+                            "Model {} of {} is not served by service {}. Served: {}.",
+                            backend.model,
+                            model.name(),
+                            backend.provider.name,
+                            names.join(", ")
+                        );
+                    }
+                }
+            }
+        }
+        if config.auto_register() {
+            for service in config.services() {
+                for name in served.get(&service.name).into_iter().flatten() {
+                    if !models.keys().any(|m| same_model(m, name)) {
+                        log::info!(
+                            "Registered model {} found on service {}.",
+                            name,
+                            service.name
+                        );
+                        let model = config.to_discovered_model(name, service);
+                        models.insert(name.clone(), Arc::new(model));
+                    }
+                }
+            }
+        }
+        let tools = available_tools();
+        Ok(Runtime {
+            config,
+            models,
+            served,
+            tools,
+            queue: TaskQueue::new(),
+            tasks: Vec::new(),
+        })
+    }
+
+    // Query models of services with discovery on. Services failing to answer are skipped, as
+    // they may come back before they are needed.
+    async fn discover(config: &Config) -> HashMap<String, Vec<String>> {
+        let mut served = HashMap::new();
+        for service in config.services().iter().filter(|s| s.discover()) {
+            match service.list_models().await {
+                Ok(names) => {
+                    served.insert(service.name.clone(), names);
+                }
+                Err(e) => log::warn!("Discover models of service {}: {}", service.name, e),
+            }
+        }
+        served
+    }
+
+    // Add task to the queue. It will be scheduled on the next `run`.
+    // A task of the same name as a scheduled one, e.g. resumed from its checkpoint, is skipped
+    // as both would share one transcript and checkpoint.
+    pub fn new_task(&mut self, task: Task) -> Result<(), Box<dyn std::error::Error>> {
+        if self
+            .queue
+            .iter()
+            .chain(self.tasks.iter())
+            .any(|r| r.task.name == task.name)
+        {
+            log::warn!("Task {} is scheduled already, skip it.", task.name);
+            return Ok(());
+        }
+        let r = RuntimeTask::from_task(self, task)?;
+        // A new run starts with a fresh transcript, and a checkpoint replacing the one of the
+        // previous run.
+        if let Some(transcript) = &r.transcript {
+            if let Some(path) = transcript.rotate()? {
+                log::info!(
+                    "Transcript of the previous run of task {} moved to {}.",
+                    r.task.name,
+                    path.display()
+                );
+            }
+        }
+        r.save()?;
+        self.queue.push(r.task.priority, r);
+        Ok(())
+    }
+
+    // Queue unfinished tasks checkpointed in the transcript directory. Returns names of them.
+    pub fn resume(&mut self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let Some(dir) = self.config.transcript_dir() else {
+            return Ok(Vec::new());
+        };
+        let mut resumed = Vec::new();
+        for path in Checkpoint::list(dir)? {
+            let checkpoint = Checkpoint::load(&path)?;
+            if let RuntimeTaskStatus::Ended(_) = checkpoint.status {
+                continue;
+            }
+            let r = RuntimeTask::from_checkpoint(self, checkpoint)?;
+            log::info!(
+                "Resuming task {} from iteration {}.",
+                r.task.name,
+                r.iterations
+            );
+            resumed.push(r.task.name.clone());
+            self.queue.push(r.task.priority, r);
+        }
+        Ok(resumed)
+    }
+
+    // Schedule queued tasks until all of them end.
+    // Tasks are stepped one iteration at a time and put back to the queue in between, so the
+    // model is shared by priority and in FIFO order among tasks of the same priority. No more
+    // than `max_concurrency` iterations run on one model concurrently.
+    pub async fn run(&mut self) {
+        let limits: HashMap<String, usize> = self
+            .models
+            .iter()
+            .map(|(name, m)| (name.clone(), m.max_concurrency()))
+            .collect();
+        let mut busy: HashMap<String, usize> = HashMap::new();
+        let mut running = FuturesUnordered::new();
+        loop {
+            while let Some(mut runtime_task) = self.queue.pop_ready(|t| {
+                busy.get(&t.task.model_name).copied().unwrap_or(0)
+                    < limits.get(&t.task.model_name).copied().unwrap_or(1)
+            }) {
+                let model_name = runtime_task.task.model_name.clone();
+                *busy.entry(model_name.clone()).or_default() += 1;
+                runtime_task.status = RuntimeTaskStatus::Running;
+                let handle = tokio::spawn(async move {
+                    // Only consistent states between iterations are checkpointed. An iteration
+                    // failed on an unreachable service is resumed from the previous one, any
+                    // other failure ends the task for good.
+                    let result = match runtime_task.step().await {
+                        Ok(ended) => runtime_task.save().map(|_| ended).map_err(Into::into),
+                        Err(e) if is_transient(e.as_ref()) => Err(e),
+                        Err(e) => {
+                            runtime_task.status = RuntimeTaskStatus::Ended(false);
+                            if let Err(save) = runtime_task.save() {
+                                log::error!("Checkpoint task {}: {}", runtime_task.task.name, save);
+                            }
+                            Err(e)
+                        }
+                    };
+                    (runtime_task, result)
+                });
+                running.push(async move { (model_name, handle.await) });
+            }
+            // Nothing running means nothing could be dispatched either. All done.
+            let Some((model_name, joined)) = running.next().await else {
+                break;
+            };
+            if let Some(n) = busy.get_mut(&model_name) {
+                *n -= 1;
+            }
+            let (mut runtime_task, result) = match joined {
+                Ok(stepped) => stepped,
+                Err(e) => {
+                    log::error!(
+                        "Task on model {} panicked and is dropped: {}",
+                        model_name,
+                        e
+                    );
+                    continue;
+                }
+            };
+            match result {
+                Ok(true) => {
+                    log::info!(
+                        "Task {} ended after {} iterations, using {}.",
+                        runtime_task.task.name,
+                        runtime_task.iterations,
+                        runtime_task.usage
+                    );
+                    self.tasks.push(runtime_task);
+                }
+                Ok(false) => {
+                    runtime_task.status = RuntimeTaskStatus::Waiting;
+                    self.queue.push(runtime_task.task.priority, runtime_task);
+                }
+                Err(e) => {
+                    log::error!(
+                        "Task {} aborted after using {}: {}",
+                        runtime_task.task.name,
+                        runtime_task.usage,
+                        e
+                    );
+                    runtime_task.status = RuntimeTaskStatus::Ended(false);
+                    self.tasks.push(runtime_task);
+                }
+            }
+        }
+    }
+
+    // Usage of every model by all tasks so far.
+    pub fn usage(&self) -> Vec<(&str, TokenUsage)> {
+        let mut usage: Vec<_> = self
+            .models
+            .iter()
+            .map(|(name, model)| (name.as_str(), model.usage()))
+            .collect();
+        usage.sort_by_key(|(name, _)| *name);
+        usage
+    }
+
+    // Ended tasks by name and whether they succeeded.
+    pub fn results(&self) -> Vec<(&str, bool)> {
+        self.tasks
+            .iter()
+            .map(|t| {
+                (
+                    t.task.name.as_str(),
+                    matches!(t.status, RuntimeTaskStatus::Ended(true)),
+                )
+            })
+            .collect()
+    }
+}
+
+// Appended when the model answers without calling any tool, to keep the loop going.
+const CONTINUE_PROMPT: &str = "Continue working on the task with the tools provided. Call task_ends once the task is finished or can not progress anymore.";
+
+struct RuntimeTask {
+    task: Task,
+    history: Vec<RuntimeHistory>,
+    // History is appended to it as well if set.
+    transcript: Option<Transcript>,
+    // Path to save checkpoint after every iteration if set.
+    checkpoint: Option<PathBuf>,
+    // Conversation sent to the model on every iteration.
+    messages: Vec<Message>,
+    model: Arc<Model>,
+    // Model to summarize older turns when the context window runs out.
+    summarizer: Arc<Model>,
+    tools: Vec<Box<dyn Tool>>,
+    status: RuntimeTaskStatus,
+    iterations: usize,
+    // Consecutive turns with failed tool calls.
+    tool_retries: usize,
+    // Tokens of the last prompt and its completion, i.e. the least size of the next prompt.
+    prompt_tokens: u64,
+    // Tokens used and their cost over all iterations, summarization included.
+    usage: TokenUsage,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+enum RuntimeTaskStatus {
+    NotStarted,
+    Running,
+    Waiting,
+    Ended(bool),
+}
+
+impl RuntimeTask {
+    pub fn from_task(runtime: &Runtime, task: Task) -> Result<Self, Box<dyn std::error::Error>> {
+        let model = runtime
+            .models
+            .get(&task.model_name)
+            .cloned()
+            .ok_or_else(|| {
+                let mut registered: Vec<_> = runtime.models.keys().map(String::as_str).collect();
+                registered.sort();
+                ModelNotRegistered::new(format!(
+                    "requested model {} not found. Registered: {}.",
+                    task.model_name,
+                    registered.join(", ")
+                ))
+            })?;
+        // Served by none of its backends anymore, as far as discovered.
+        let gone: Vec<_> = model
+            .backends()
+            .iter()
+            .filter(|b| {
+                runtime
+                    .served
+                    .get(&b.provider.name)
+                    .is_some_and(|names| !is_served(names, &b.model))
+            })
+            .collect();
+        if !gone.is_empty() && gone.len() == model.backends().len() {
+            return Err(ModelNotRegistered::new(format!(
+                "requested model {} is no longer served by service {}",
+                task.model_name,
+                gone.iter()
+                    .map(|b| b.provider.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+            .into());
+        }
+        let summarizer = match model.summarizer() {
+            Some(name) => runtime.models.get(name).cloned().ok_or_else(|| {
+                ModelNotRegistered::new(format!(
+                    "summarizer {} of model {} not found",
+                    name, task.model_name
+                ))
+            })?,
+            None => model.clone(),
+        };
+        let mut tools = Vec::new();
+        for tool_builder in task.tools.iter() {
+            tools.push(
+                runtime
+                    .tools
+                    .iter()
+                    .find(|tool| tool.name() == tool_builder.name)
+                    .ok_or_else(|| {
+                        ToolNotRegistered::new(format!(
+                            "requested tool {} not found",
+                            tool_builder.name
+                        ))
+                    })?
+                    .fork(tool_builder.args.clone())?,
+            )
+        }
+        let messages = vec![Message::new(Roles::User, task.target.clone())];
+        let transcript = runtime
+            .config
+            .transcript_dir()
+            .map(|dir| Transcript::new(dir, &task.name));
+        let checkpoint = runtime
+            .config
+            .transcript_dir()
+            .map(|dir| Checkpoint::path(dir, &task.name));
+        Ok(RuntimeTask {
+            task,
+            history: Vec::new(),
+            transcript,
+            checkpoint,
+            messages,
+            tools,
+            model,
+            summarizer,
+            status: RuntimeTaskStatus::NotStarted,
+            iterations: 0,
+            tool_retries: 0,
+            prompt_tokens: 0,
+            usage: TokenUsage::default(),
+        })
+    }
+
+    // Rebuild the task from checkpoint, with history loaded back from its transcript.
+    pub fn from_checkpoint(
+        runtime: &Runtime,
+        checkpoint: Checkpoint,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut r = RuntimeTask::from_task(runtime, checkpoint.task)?;
+        for tool in r.tools.iter() {
+            if let Some(state) = checkpoint.tools.get(tool.name()) {
+                tool.restore(state.clone())?;
+            }
+        }
+        if let Some(transcript) = &r.transcript {
+            if transcript.path().exists() {
+                r.history = Transcript::load(transcript.path())?;
+            }
+        }
+        r.messages = checkpoint.messages;
+        r.iterations = checkpoint.iterations;
+        r.tool_retries = checkpoint.tool_retries;
+        r.usage = checkpoint.usage;
+        r.status = match checkpoint.status {
+            RuntimeTaskStatus::NotStarted => RuntimeTaskStatus::NotStarted,
+            _ => RuntimeTaskStatus::Waiting,
+        };
+        Ok(r)
+    }
+
+    // Snapshot of the current state.
+    fn to_checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            task: self.task.clone(),
+            messages: self.messages.clone(),
+            tools: self
+                .tools
+                .iter()
+                .map(|t| (t.name().to_string(), t.snapshot()))
+                .collect(),
+            iterations: self.iterations,
+            tool_retries: self.tool_retries,
+            status: self.status.clone(),
+            usage: self.usage,
+        }
+    }
+
+    // Name of the budget used up, if any.
+    fn exceeded_budget(&self) -> Option<&'static str> {
+        if self
+            .task
+            .token_budget
+            .is_some_and(|budget| self.usage.total_tokens() >= budget)
+        {
+            return Some("token");
+        }
+        if self
+            .task
+            .cost_budget
+            .is_some_and(|budget| self.usage.cost >= budget)
+        {
+            return Some("cost");
+        }
+        None
+    }
+
+    // Save checkpoint if the task is configured to.
+    fn save(&self) -> Result<(), CheckpointError> {
+        match &self.checkpoint {
+            Some(path) => self.to_checkpoint().save(path),
+            None => Ok(()),
+        }
+    }
+
+    // One iteration of the reasoning loop. Query the model, conduct its tool calls and feed the
+    // results back. Returns true once the task ends or max_iterations is reached.
+    async fn step(&mut self) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if self.iterations >= self.task.max_iterations {
+            log::warn!(
+                "Task {} reached max iterations {} before ending.",
+                self.task.name,
+                self.task.max_iterations
+            );
+            self.status = RuntimeTaskStatus::Ended(false);
+            return Ok(true);
+        }
+        if let Some(exceeded) = self.exceeded_budget() {
+            log::warn!(
+                "Task {} exceeded its {} budget: {}.",
+                self.task.name,
+                exceeded,
+                self.usage
+            );
+            self.status = RuntimeTaskStatus::Ended(false);
+            return Ok(true);
+        }
+        self.iterations += 1;
+        if let Some(context_window) = self.model.context_window() {
+            if self.prompt_tokens as f64 >= context_window as f64 * compaction::COMPACT_RATIO {
+                self.compact().await?;
+            }
+        }
+        // Get response from LLM.
+        let mut response = {
+            // These resources should die early..
+            // Tools like draft render their content in front of the conversation.
+            // Tools offered in the prompt go first, and the conversation is turned into text.
+            let prompt_tools = self.model.tool_mode() == ToolMode::Prompt;
+            let prompts: Vec<_> = prompt_tools
+                .then(|| text_tools::tools_prompt(&self.tools))
+                .into_iter()
+                .chain(self.tools.iter().filter_map(|t| t.prompt()))
+                .map(|content| Message::new(Roles::System, content))
+                .collect();
+            let rendered;
+            let messages = if prompt_tools {
+                rendered = text_tools::render_messages(&self.messages);
+                &rendered
+            } else {
+                &self.messages
+            };
+            let mut sampling = self.model.sampling().merge(&self.task.sampling);
+            if prompt_tools {
+                sampling = sampling.without_tools();
+            }
+            let mut request = Request::new(self.model.name().to_string())
+                .with_sampling(sampling)
+                .add_messages(&prompts)
+                .add_messages(messages);
+            if !prompt_tools {
+                request = request.add_tools(&self.tools);
+            }
+            let request = &request;
+            let time = SystemTime::now();
+            let start = Instant::now();
+            let bytes = self.request_model(request).await?;
+            // Raw response is recorded even if it cannot be parsed.
+            let response = Response::from_u8(&bytes);
+            let history = RuntimeHistory {
+                time,
+                elapsed: start.elapsed(),
+                kind: HistoryKind::Model,
+                request: request.format().await,
+                response: String::from_utf8_lossy(&bytes).into_owned(),
+                reasoning: response
+                    .as_ref()
+                    .ok()
+                    .and_then(|r| r.reasoning(self.model.reasoning())),
+            };
+            self.record(history)?;
+            response?
+        };
+        response.parse_text_tool_calls(
+            self.model.text_tool_calls(),
+            self.model.reasoning(),
+            self.iterations,
+        );
+        self.prompt_tokens = response.usage().total_tokens;
+        self.usage.add(&self.model.record_usage(response.usage()));
+        match response.finish_reason() {
+            Some(FinishReason::Length) => {
+                log::warn!("Task {}: response cut at the token limit.", self.task.name)
+            }
+            Some(FinishReason::ContentFilter) => log::warn!(
+                "Task {}: response stopped by content filter.",
+                self.task.name
+            ),
+            _ => {}
+        }
+        if let Some(refusal) = response.refusal() {
+            log::warn!("Task {}: model refused: {}", self.task.name, refusal);
+        }
+        let tool_calls = response.tool_calls();
+        self.messages
+            .push(response.message_with(self.model.reasoning()));
+        if tool_calls.is_empty() {
+            self.messages
+                .push(Message::new(Roles::User, CONTINUE_PROMPT.to_string()));
+            return Ok(false);
+        }
+        // Conduct tool calls concurrently and feed results back in the order of calls. Errors are
+        // fed back as well so the model could correct itself.
+        let calls: Vec<_> = tool_calls.iter().map(|t| self.call_tool(t)).collect();
+        let outputs: Vec<_> = futures::stream::iter(calls)
+            .buffered(self.task.max_parallel_tool_calls.max(1))
+            .collect()
+            .await;
+        let mut failed = false;
+        for (tool_call, (time, elapsed, result)) in tool_calls.into_iter().zip(outputs) {
+            let content = result.unwrap_or_else(|e| {
+                log::warn!("Task {} tool call failed: {}", self.task.name, e);
+                failed = true;
+                json!({ "error": e.to_string() }).to_string()
+            });
+            self.record(RuntimeHistory {
+                time,
+                elapsed,
+                kind: HistoryKind::Tool,
+                request: serde_json::to_string(&tool_call)?,
+                response: content.clone(),
+                reasoning: None,
+            })?;
+            self.messages.push(Message {
+                tool_call_id: Some(tool_call.id),
+                ..Message::new(Roles::Tool, content)
+            });
+        }
+        if failed {
+            self.tool_retries += 1;
+            if self.tool_retries > self.task.max_tool_retries {
+                self.status = RuntimeTaskStatus::Ended(false);
+                return Err(Box::new(ToolCallingError::new(format!(
+                    "task {} exceeded tool retry budget {}",
+                    self.task.name, self.task.max_tool_retries
+                ))));
+            }
+        } else {
+            self.tool_retries = 0;
+        }
+        // TODO: The message resulting tools. human_intervene tool.
+        if let Some(is_success) = self.tools.iter().find_map(|t| t.ending()) {
+            self.status = RuntimeTaskStatus::Ended(is_success);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // Raw response body of the model. Streamed responses are logged as they come and
+    // assembled into the same body as unstreamed ones.
+    async fn request_model(&self, request: &Request<'_>) -> Result<Vec<u8>, ProviderError> {
+        if !self.model.stream() {
+            return self.model.do_request(request).await;
+        }
+        let mut events = std::pin::pin!(self.model.do_request_stream(request).await?);
+        while let Some(event) = events.next().await {
+            match event? {
+                StreamEvent::Delta(delta) => log::debug!("Task {}: {}", self.task.name, delta),
+                StreamEvent::ReasoningDelta(delta) => {
+                    log::debug!("Task {} reasoning: {}", self.task.name, delta)
+                }
+                StreamEvent::ToolCallDelta {
+                    index,
+                    name: Some(name),
+                    ..
+                } => log::debug!("Task {} tool call #{}: {}", self.task.name, index, name),
+                StreamEvent::ToolCallDelta { arguments, .. } => {
+                    log::trace!("Task {}: {}", self.task.name, arguments)
+                }
+                StreamEvent::Done(response) => {
+                    return serde_json::to_vec(&response)
+                        .map_err(|e| ProviderError::Decode(e.to_string()))
+                }
+            }
+        }
+        Err(ProviderError::Transport(
+            "stream ended without response".to_string(),
+        ))
+    }
+
+    // Summarize turns before the latest ones into a single message. The original target stays
+    // as the first message, and draft content is rendered to prompt apart from the conversation.
+    async fn compact(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(split) = compaction::split_point(&self.messages, compaction::KEEP_TURNS) else {
+            return Ok(());
+        };
+        let summary = {
+            let messages = [
+                Message::new(Roles::System, compaction::SUMMARY_PROMPT.to_string()),
+                Message::new(Roles::User, compaction::render(&self.messages[1..split])),
+            ];
+            // The summary is plain text, whatever format the model answers tasks in.
+            let sampling = Sampling {
+                response_format: None,
+                ..self.summarizer.sampling().without_tools()
+            };
+            let request = &Request::new(self.summarizer.name().to_string())
+                .with_sampling(sampling)
+                .add_messages(&messages);
+            let time = SystemTime::now();
+            let start = Instant::now();
+            let bytes = self.summarizer.do_request(request).await?;
+            self.record(RuntimeHistory {
+                time,
+                elapsed: start.elapsed(),
+                kind: HistoryKind::Summary,
+                request: request.format().await,
+                response: String::from_utf8_lossy(&bytes).into_owned(),
+                reasoning: None,
+            })?;
+            let response = Response::from_u8(&bytes)?;
+            self.usage
+                .add(&self.summarizer.record_usage(response.usage()));
+            response.content_with(self.summarizer.reasoning())
+        };
+        log::info!(
+            "Task {} compacted {} messages at {} prompt tokens.",
+            self.task.name,
+            split - 1,
+            self.prompt_tokens
+        );
+        self.messages.splice(
+            1..split,
+            [Message::new(
+                Roles::User,
+                format!("Summary of earlier progress:\n{}", summary),
+            )],
+        );
+        self.prompt_tokens = 0;
+        Ok(())
+    }
+
+    // Keep history in memory and persist it to transcript.
+    fn record(&mut self, history: RuntimeHistory) -> Result<(), TranscriptError> {
+        if let Some(transcript) = &self.transcript {
+            transcript.append(&history)?;
+        }
+        self.history.push(history);
+        Ok(())
+    }
+
+    // Dispatch a single tool call to its tool, bounded by the task tool call timeout.
+    async fn call_tool(
+        &self,
+        tool_call: &ToolCall,
+    ) -> (SystemTime, Duration, Result<String, ToolCallingError>) {
+        let time = SystemTime::now();
+        let start = Instant::now();
+        let call = async {
+            match self
+                .tools
+                .iter()
+                .find(|t| t.function_name() == tool_call.function.name)
+            {
+                Some(tool) => tool.call(tool_call.function.arguments.clone()).await,
+                None => Err(ToolCallingError::new(format!(
+                    "required tool not found: {}. Available tools: {}",
+                    tool_call.function.name,
+                    self.tools
+                        .iter()
+                        .map(|t| t.function_name())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))),
+            }
+        };
+        let result = match self.task.tool_call_timeout {
+            Some(secs) => tokio::time::timeout(Duration::from_secs(secs), call)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(ToolCallingError::new(format!(
+                        "tool call {} timed out after {} seconds",
+                        tool_call.function.name, secs
+                    )))
+                }),
+            None => call.await,
+        };
+        (time, start.elapsed(), result)
+    }
+}
+
+// Ollama lists models with their tag, and serves `name` as `name:latest`.
+fn same_model(a: &str, b: &str) -> bool {
+    a.strip_suffix(":latest").unwrap_or(a) == b.strip_suffix(":latest").unwrap_or(b)
+}
+
+fn is_served(names: &[String], model: &str) -> bool {
+    names.iter().any(|name| same_model(name, model))
+}
+
+// Failure of the service rather than of the task, worth resuming once it is back.
+fn is_transient(e: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    e.downcast_ref::<ProviderError>()
+        .is_some_and(ProviderError::retryable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn runtime(transcript_dir: Option<&std::path::Path>) -> Runtime {
+        let config: Config = serde_json::from_value(json!({
+            "models": [{ "name": "mock", "provider": "mock" }],
+            "services": [{ "name": "mock", "ip": "127.0.0.1", "port": 1 }],
+            "transcript_dir": transcript_dir,
+        }))
+        .unwrap();
+        Runtime::init(config).await.unwrap()
+    }
+
+    async fn runtime_task(task: serde_json::Value) -> RuntimeTask {
+        RuntimeTask::from_task(&runtime(None).await, serde_json::from_value(task).unwrap()).unwrap()
+    }
+
+    fn tool_call(id: &str, name: &str, arguments: serde_json::Value) -> ToolCall {
+        serde_json::from_value(json!({
+            "id": id,
+            "type": "function",
+            "function": { "name": name, "arguments": arguments.to_string() }
+        }))
+        .unwrap()
+    }
+
+    // Answer of the model calling one tool.
+    fn calling(id: &str, name: &str, arguments: &str) -> crate::provider::mock::Reply {
+        crate::provider::mock::Reply::json(json!({
+            "choices": [{
+                "finish_reason": "tool_calls",
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": id,
+                        "type": "function",
+                        "function": { "name": name, "arguments": arguments }
+                    }]
+                }
+            }]
+        }))
+    }
+
+    // Task on a model served by the mock server.
+    async fn served_task(addr: std::net::SocketAddr, task: serde_json::Value) -> RuntimeTask {
+        let config: Config = serde_json::from_value(json!({
+            "models": [{ "name": "mock", "provider": "mock" }],
+            "services": [{ "name": "mock", "ip": addr.ip().to_string(), "port": addr.port() }],
+        }))
+        .unwrap();
+        let runtime = Runtime::init(config).await.unwrap();
+        RuntimeTask::from_task(&runtime, serde_json::from_value(task).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_call_tool_timeout() {
+        let task = runtime_task(json!({
+            "name": "timeout",
+            "model": "mock",
+            "target": "",
+            "tools": [{ "name": "shell", "args": [] }],
+            "max_iterations": 1,
+            "tool_call_timeout": 1
+        }))
+        .await;
+
+        let sleep = tool_call(
+            "1",
+            "search_products",
+            json!({ "executable": "sleep", "args": ["5"] }),
+        );
+        let (_, elapsed, result) = task.call_tool(&sleep).await;
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        assert!(elapsed < Duration::from_secs(5));
+
+        let unknown = tool_call("2", "unknown", json!({}));
+        let (_, _, result) = task.call_tool(&unknown).await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("required tool not found"));
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("checkpoint-{}", std::process::id()));
+        let task = json!({
+            "name": "resume",
+            "model": "mock",
+            "target": "keep notes",
+            "tools": [{ "name": "draft", "args": [] }, { "name": "taskEnds", "args": [] }],
+            "max_iterations": 10
+        });
+        let mut r = RuntimeTask::from_task(
+            &runtime(Some(&dir)).await,
+            serde_json::from_value(task).unwrap(),
+        )
+        .unwrap();
+        let note = tool_call(
+            "1",
+            "record_content",
+            json!({ "content": "found Cargo.toml" }),
+        );
+        r.call_tool(&note).await.2.unwrap();
+        r.iterations = 3;
+        r.status = RuntimeTaskStatus::Running;
+        r.save().unwrap();
+
+        let mut restarted = runtime(Some(&dir)).await;
+        let resumed = restarted.resume().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(resumed, vec!["resume".to_string()]);
+
+        let r = restarted.queue.pop_ready(|_| true).unwrap();
+        assert_eq!(r.iterations, 3);
+        assert!(matches!(r.status, RuntimeTaskStatus::Waiting));
+        assert_eq!(r.messages.len(), 1);
+        assert_eq!(r.messages[0].content, "keep notes");
+        let draft = r.tools.iter().find(|t| t.name() == "draft").unwrap();
+        assert_eq!(draft.snapshot()["buffer"], "found Cargo.toml");
+    }
+
+    #[tokio::test]
+    async fn test_budget_ends_task() {
+        let (addr, server) =
+            crate::provider::mock::serve(vec![crate::provider::mock::Reply::json(json!({
+                "choices": [{ "message": { "role": "assistant", "content": "thinking" } }],
+                "usage": { "prompt_tokens": 800, "completion_tokens": 200, "total_tokens": 1000 }
+            }))])
+            .await;
+        let config: Config = serde_json::from_value(json!({
+            "models": [{
+                "name": "priced",
+                "provider": "mock",
+                "pricing": { "prompt": 1.0, "completion": 4.0 }
+            }],
+            "services": [{ "name": "mock", "ip": addr.ip().to_string(), "port": addr.port() }],
+        }))
+        .unwrap();
+        let runtime = Runtime::init(config).await.unwrap();
+        let task = serde_json::from_value(json!({
+            "name": "budget",
+            "model": "priced",
+            "target": "",
+            "tools": [],
+            "max_iterations": 10,
+            "cost_budget": 0.001
+        }))
+        .unwrap();
+        let mut task = RuntimeTask::from_task(&runtime, task).unwrap();
+
+        assert!(!task.step().await.unwrap());
+        server.await.unwrap();
+        assert_eq!(task.usage.total_tokens(), 1000);
+        assert!((task.usage.cost - 0.0016).abs() < 1e-9);
+        assert_eq!(runtime.usage()[0].1, task.usage);
+
+        assert!(task.step().await.unwrap());
+        assert!(matches!(task.status, RuntimeTaskStatus::Ended(false)));
+        assert_eq!(task.iterations, 1);
+    }
+
+    #[tokio::test]
+    async fn test_discover_models() {
+        let (addr, server) =
+            crate::provider::mock::serve(vec![crate::provider::mock::Reply::json(json!({
+                "object": "list",
+                "data": [{ "id": "llama3", "object": "model" }, { "id": "qwen2" }]
+            }))])
+            .await;
+        let config: Config = serde_json::from_value(json!({
+            "models": [{
+                "name": "chat",
+                "backends": [{ "provider": "mock", "model": "mistral" }]
+            }],
+            "services": [{
+                "name": "mock",
+                "ip": addr.ip().to_string(),
+                "port": addr.port(),
+                "discover": true
+            }],
+            "auto_register": true,
+        }))
+        .unwrap();
+
+        let runtime = Runtime::init(config).await.unwrap();
+        let mut names: Vec<_> = runtime.models.keys().cloned().collect();
+        names.sort();
+        assert_eq!(names, vec!["chat", "llama3", "qwen2"]);
+        let captured = server.await.unwrap();
+        assert_eq!(captured.len(), 1);
+        assert_eq!(captured[0].method, "GET");
+        assert_eq!(captured[0].path, "/v1/models");
+
+        let task = |model: &str| {
+            serde_json::from_value(json!({
+                "name": "discover",
+                "model": model,
+                "target": "",
+                "tools": [],
+                "max_iterations": 1
+            }))
+            .unwrap()
+        };
+        assert!(RuntimeTask::from_task(&runtime, task("qwen2")).is_ok());
+        let err = RuntimeTask::from_task(&runtime, task("chat"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no longer served by service mock"));
+        let err = RuntimeTask::from_task(&runtime, task("gone"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Registered: chat, llama3, qwen2."));
+    }
+
+    #[tokio::test]
+    async fn test_failed_task_not_resumed() {
+        let dir = std::env::temp_dir().join(format!("aborted-{}", std::process::id()));
+        let (addr, server) =
+            crate::provider::mock::serve(vec![crate::provider::mock::Reply::status(
+                400,
+                "bad request",
+            )])
+            .await;
+        let config = |dir: &std::path::Path| -> Config {
+            serde_json::from_value(json!({
+                "models": [{ "name": "mock", "provider": "mock" }],
+                "services": [{ "name": "mock", "ip": addr.ip().to_string(), "port": addr.port() }],
+                "transcript_dir": dir,
+            }))
+            .unwrap()
+        };
+        let mut runtime = Runtime::init(config(&dir)).await.unwrap();
+        let task = serde_json::from_value(json!({
+            "name": "aborted",
+            "model": "mock",
+            "target": "",
+            "tools": [],
+            "max_iterations": 10
+        }))
+        .unwrap();
+        runtime.new_task(task).unwrap();
+        runtime.run().await;
+        server.await.unwrap();
+        assert_eq!(runtime.results(), vec![("aborted", false)]);
+
+        let checkpoint = Checkpoint::load(Checkpoint::path(&dir, "aborted")).unwrap();
+        let mut restarted = Runtime::init(config(&dir)).await.unwrap();
+        let resumed = restarted.resume().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(checkpoint.status, RuntimeTaskStatus::Ended(false)));
+        assert!(resumed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_discover_latest_tag() {
+        let (addr, server) =
+            crate::provider::mock::serve(vec![crate::provider::mock::Reply::json(json!({
+                "models": [{ "name": "llama3:latest" }, { "name": "qwen2:7b" }]
+            }))])
+            .await;
+        let config: Config = serde_json::from_value(json!({
+            "models": [{ "name": "llama3", "provider": "ollama" }],
+            "services": [{
+                "name": "ollama",
+                "kind": "ollama",
+                "ip": addr.ip().to_string(),
+                "port": addr.port(),
+                "discover": true
+            }],
+            "auto_register": true,
+        }))
+        .unwrap();
+        let runtime = Runtime::init(config).await.unwrap();
+        assert_eq!(server.await.unwrap()[0].path, "/api/tags");
+
+        // Configured `llama3` is the listed `llama3:latest`, neither gone nor registered twice.
+        let mut names: Vec<_> = runtime.models.keys().cloned().collect();
+        names.sort();
+        assert_eq!(names, vec!["llama3", "qwen2:7b"]);
+        let task = serde_json::from_value(json!({
+            "name": "latest",
+            "model": "llama3",
+            "target": "",
+            "tools": [],
+            "max_iterations": 1
+        }))
+        .unwrap();
+        assert!(RuntimeTask::from_task(&runtime, task).is_ok());
+        assert!(!same_model("llama3", "llama3:8b"));
+    }
+
+    #[tokio::test]
+    async fn test_new_run_starts_fresh() {
+        let dir = std::env::temp_dir().join(format!("rerun-{}", std::process::id()));
+        let task = || {
+            serde_json::from_value(json!({
+                "name": "rerun",
+                "model": "mock",
+                "target": "",
+                "tools": [],
+                "max_iterations": 1
+            }))
+            .unwrap()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("rerun.jsonl"), "{}\n").unwrap();
+
+        let mut runtime = runtime(Some(&dir)).await;
+        runtime.new_task(task()).unwrap();
+        runtime.new_task(task()).unwrap();
+        let transcripts: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|n| n.ends_with(".jsonl"))
+            .collect();
+        let checkpoint = Checkpoint::load(Checkpoint::path(&dir, "rerun")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(runtime.queue.iter().count(), 1);
+        // The previous transcript is kept aside, and the new run has none yet.
+        assert_eq!(transcripts.len(), 1);
+        assert!(transcripts[0].starts_with("rerun.") && transcripts[0] != "rerun.jsonl");
+        assert!(matches!(checkpoint.status, RuntimeTaskStatus::NotStarted));
+    }
+
+    #[tokio::test]
+    async fn test_task_ends_by_call() {
+        let (addr, server) = crate::provider::mock::serve(vec![calling(
+            "call-1",
+            "task_ends",
+            r#"{"is_success": true, "explanation": "done"}"#,
+        )])
+        .await;
+        let mut task = served_task(
+            addr,
+            json!({
+                "name": "ends",
+                "model": "mock",
+                "target": "",
+                "tools": [{ "name": "taskEnds", "args": [] }],
+                "max_iterations": 10
+            }),
+        )
+        .await;
+
+        assert!(task.step().await.unwrap());
+        server.await.unwrap();
+        assert!(matches!(task.status, RuntimeTaskStatus::Ended(true)));
+        assert_eq!(task.iterations, 1);
+    }
+
+    #[tokio::test]
+    async fn test_tool_errors_fed_back() {
+        let (addr, server) = crate::provider::mock::serve(vec![
+            calling("call-1", "unknown", "{}"),
+            calling("call-2", "task_ends", "not json"),
+        ])
+        .await;
+        let mut task = served_task(
+            addr,
+            json!({
+                "name": "retries",
+                "model": "mock",
+                "target": "",
+                "tools": [{ "name": "taskEnds", "args": [] }],
+                "max_iterations": 10,
+                "max_tool_retries": 1
+            }),
+        )
+        .await;
+        let error = |message: &Message| -> String {
+            let content: serde_json::Value = serde_json::from_str(&message.content).unwrap();
+            content["error"].as_str().unwrap().to_string()
+        };
+
+        // Unknown tool is answered with an error, and the task goes on.
+        assert!(!task.step().await.unwrap());
+        let fed_back = task.messages.last().unwrap();
+        assert!(matches!(fed_back.role, Roles::Tool));
+        assert_eq!(fed_back.tool_call_id.as_deref(), Some("call-1"));
+        assert!(error(fed_back).contains("required tool not found: unknown"));
+        assert_eq!(task.tool_retries, 1);
+
+        // Bad arguments are answered too, and exceed the retry budget.
+        let aborted = task.step().await.unwrap_err();
+        server.await.unwrap();
+        assert!(aborted.to_string().contains("exceeded tool retry budget 1"));
+        assert!(matches!(task.status, RuntimeTaskStatus::Ended(false)));
+        let fed_back = task.messages.last().unwrap();
+        assert_eq!(fed_back.tool_call_id.as_deref(), Some("call-2"));
+        assert!(!error(fed_back).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_prompt_mode_request() {
+        let (addr, server) =
+            crate::provider::mock::serve(vec![crate::provider::mock::Reply::json(json!({
+                "choices": [{ "message": { "role": "assistant", "content": "thinking" } }]
+            }))])
+            .await;
+        let config: Config = serde_json::from_value(json!({
+            "models": [{
+                "name": "mock",
+                "provider": "mock",
+                "tool_mode": "prompt",
+                "sampling": { "tool_choice": "required" }
+            }],
+            "services": [{ "name": "mock", "ip": addr.ip().to_string(), "port": addr.port() }],
+        }))
+        .unwrap();
+        let runtime = Runtime::init(config).await.unwrap();
+        let task = serde_json::from_value(json!({
+            "name": "prompt",
+            "model": "mock",
+            "target": "",
+            "tools": [{ "name": "taskEnds", "args": [] }],
+            "max_iterations": 10
+        }))
+        .unwrap();
+        let mut task = RuntimeTask::from_task(&runtime, task).unwrap();
+
+        assert!(!task.step().await.unwrap());
+        let captured = server.await.unwrap().pop().unwrap();
+        let body: serde_json::Value = serde_json::from_str(&captured.body).unwrap();
+        // Tools are only described in the system prompt.
+        assert!(body.get("tools").is_none());
+        assert!(body.get("tool_choice").is_none());
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert!(body["messages"][0]["content"]
+            .as_str()
+            .unwrap()
+            .contains("task_ends"));
+    }
+
+    #[tokio::test]
+    async fn test_replay_text_tool_calls() {
+        let dir = std::env::temp_dir().join(format!("replay-text-{}", std::process::id()));
+        let answer = |content: &str| {
+            crate::provider::mock::Reply::json(json!({
+                "choices": [{ "message": { "role": "assistant", "content": content } }]
+            }))
+        };
+        let (addr, server) = crate::provider::mock::serve(vec![
+            answer("<tool_call>{\"name\": \"unknown\", \"arguments\": {}}</tool_call>"),
+            answer("done"),
+        ])
+        .await;
+        let config = |mode: &str| -> Config {
+            serde_json::from_value(json!({
+                "models": [{ "name": "mock", "provider": "mock", "text_tool_calls": "hermes" }],
+                "services": [{ "name": "mock", "ip": addr.ip().to_string(), "port": addr.port() }],
+                "cache": { "dir": dir, "mode": mode },
+            }))
+            .unwrap()
+        };
+        let task = || {
+            serde_json::from_value(json!({
+                "name": "replay",
+                "model": "mock",
+                "target": "list files",
+                "tools": [],
+                "max_iterations": 10
+            }))
+            .unwrap()
+        };
+
+        let recording = Runtime::init(config("record")).await.unwrap();
+        let mut recorded = RuntimeTask::from_task(&recording, task()).unwrap();
+        assert!(!recorded.step().await.unwrap());
+        assert!(!recorded.step().await.unwrap());
+        server.await.unwrap();
+
+        // Ids of parsed calls are the same on replay, so the second request is found too.
+        let replaying = Runtime::init(config("replay")).await.unwrap();
+        let mut replayed = RuntimeTask::from_task(&replaying, task()).unwrap();
+        let first = replayed.step().await;
+        let second = replayed.step().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!first.unwrap());
+        assert!(!second.unwrap());
+        assert_eq!(
+            replayed.messages[2].tool_call_id,
+            recorded.messages[2].tool_call_id
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+// Queue of tasks waiting for their turn on the model.
+// Higher priority goes first, and FIFO among the same priority.
+pub struct TaskQueue<T> {
+    heap: BinaryHeap<Queued<T>>,
+    // Monotonic counter to keep FIFO order among equal priorities.
+    seq: u64,
+}
+
+struct Queued<T> {
+    priority: i32,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Queued<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+impl<T> Eq for Queued<T> {}
+
+impl<T> PartialOrd for Queued<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Queued<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Earlier pushed is greater, so it pops first from max-heap.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl<T> TaskQueue<T> {
+    pub fn new() -> Self {
+        TaskQueue {
+            heap: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    pub fn push(&mut self, priority: i32, item: T) {
+        self.heap.push(Queued {
+            priority,
+            seq: self.seq,
+            item,
+        });
+        self.seq += 1;
+    }
+
+    // Queued items in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.heap.iter().map(|queued| &queued.item)
+    }
+
+    // Pop the first item in order accepted by `ready`. Skipped items keep their place.
+    pub fn pop_ready<F>(&mut self, ready: F) -> Option<T>
+    where
+        F: Fn(&T) -> bool,
+    {
+        let mut skipped = Vec::new();
+        let mut found = None;
+        while let Some(queued) = self.heap.pop() {
+            if ready(&queued.item) {
+                found = Some(queued.item);
+                break;
+            }
+            skipped.push(queued);
+        }
+        self.heap.extend(skipped);
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_then_fifo() {
+        let mut queue = TaskQueue::new();
+        queue.push(0, "a");
+        queue.push(1, "b");
+        queue.push(0, "c");
+        queue.push(1, "d");
+
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop_ready(|_| true)).collect();
+        assert_eq!(order, vec!["b", "d", "a", "c"]);
+    }
+
+    #[test]
+    fn test_pop_ready_skips_busy() {
+        let mut queue = TaskQueue::new();
+        queue.push(1, "busy");
+        queue.push(0, "free");
+
+        assert_eq!(queue.pop_ready(|item| *item != "busy"), Some("free"));
+        assert_eq!(queue.pop_ready(|item| *item != "busy"), None);
+        assert_eq!(queue.pop_ready(|_| true), Some("busy"));
+    }
+}
@@ -2,10 +2,11 @@ use std::{fs::File, io::BufReader, path::Path};
 
 use crate::{
     model::Model,
+    provider::sampling::Sampling,
     tool::{Tool, ToolBuilder},
 };
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct Task {
     pub name: String,
     #[serde(rename = "model")]
@@ -13,6 +14,35 @@ pub struct Task {
     pub target: String,
     pub tools: Vec<ToolBuilder>,
     pub max_iterations: usize,
+    // Higher priority gets the model first. Tasks of the same priority are served in FIFO order.
+    #[serde(default)]
+    pub priority: i32,
+    // Consecutive turns with failed tool calls tolerated before the task is aborted.
+    #[serde(default = "default_max_tool_retries")]
+    pub max_tool_retries: usize,
+    // Tool calls of one turn conducted at the same time.
+    #[serde(default = "default_max_parallel_tool_calls")]
+    pub max_parallel_tool_calls: usize,
+    // Seconds before a single tool call is abandoned. No limit if not set.
+    #[serde(default)]
+    pub tool_call_timeout: Option<u64>,
+    // Tokens or cost the task may use in total, summarization included. The task ends
+    // unsuccessfully once either is exceeded.
+    #[serde(default)]
+    pub token_budget: Option<u64>,
+    #[serde(default)]
+    pub cost_budget: Option<f64>,
+    // Overrides sampling parameters of the model for this task.
+    #[serde(default)]
+    pub sampling: Sampling,
+}
+
+fn default_max_tool_retries() -> usize {
+    3
+}
+
+fn default_max_parallel_tool_calls() -> usize {
+    4
 }
 
 impl Task {
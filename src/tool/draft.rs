@@ -1,12 +1,23 @@
+use std::sync::Mutex;
+
 use async_trait::async_trait;
 
-use crate::{provider::ToolCallFunction, utils::ToolCallingError};
+use crate::utils::{ToolCallingError, ToolRestoringError};
 
 use super::{Tool, ToolBuilder};
 
-#[derive(Clone)]
 pub struct Draft {
     base: ToolBuilder,
+    buffer: Mutex<String>,
+    plan: Mutex<String>,
+}
+
+// Max characters of the scratch paper put to prompt. Notes beyond are cut off, then plan.
+const MAX_PROMPT_CHARS: usize = 8000;
+
+// Kept in checkpoint.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct State {
     buffer: String,
     plan: String,
 }
@@ -63,42 +74,124 @@ impl Tool for Draft {
         })
     }
 
-    async fn call(&mut self, arg_string: String) -> Result<String, crate::utils::ToolCallingError> {
+    async fn call(&self, arg_string: String) -> Result<String, crate::utils::ToolCallingError> {
         let call_args: CallArgs = serde_json::from_str(&arg_string).map_err(|e| {
-            crate::utils::ToolCallingError::new(format!(
-                "Calling {} error: {}",
-                self.name(),
-                e.to_string()
-            ))
+            crate::utils::ToolCallingError::new(format!("Calling {} error: {}", self.name(), e))
         })?;
 
-        self.buffer = call_args.content;
-
         if let Some(p) = call_args.plan {
             if p.if_update {
                 if let Some(content) = p.full_content {
-                    self.plan = content;
+                    *self.plan.lock().unwrap() = content;
                 } else {
                     return Err(ToolCallingError::new("illegal tool call: plan.full_content should not be none if plan.if_update is true.".to_string()));
                 }
             }
         }
 
+        *self.buffer.lock().unwrap() = call_args.content;
+
         // No need to give feed back in case successful. The content will be put to prompt.
         Ok("".to_string())
     }
 
-    fn fork(&self, args: Vec<String>) -> Result<Box<dyn Tool>, crate::utils::ToolForkingError> {
-        Ok(Box::new(self.clone()))
+    fn fork(&self, _args: Vec<String>) -> Result<Box<dyn Tool>, crate::utils::ToolForkingError> {
+        Ok(Box::new(Into::<Draft>::into(self.base.clone())))
+    }
+
+    fn prompt(&self) -> Option<String> {
+        Some(render(
+            &self.plan.lock().unwrap(),
+            &self.buffer.lock().unwrap(),
+            MAX_PROMPT_CHARS,
+        ))
+    }
+
+    fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!(State {
+            buffer: self.buffer.lock().unwrap().clone(),
+            plan: self.plan.lock().unwrap().clone(),
+        })
+    }
+
+    fn restore(&self, state: serde_json::Value) -> Result<(), ToolRestoringError> {
+        let state: State = serde_json::from_value(state).map_err(|e| {
+            ToolRestoringError::new(format!("Restoring {} error: {}", self.name(), e))
+        })?;
+        *self.buffer.lock().unwrap() = state.buffer;
+        *self.plan.lock().unwrap() = state.plan;
+        Ok(())
+    }
+}
+
+// Render the scratch paper in at most `max_chars`. Plan is kept first as it steers the task,
+// and notes get the room left.
+fn render(plan: &str, buffer: &str, max_chars: usize) -> String {
+    let head = |plan: &str| {
+        format!(
+            "# Scratch paper\n\
+            Everything you recorded by record_content. It is all you remember from former rounds.\n\n\
+            ## Plan\n{}\n\n## Notes\n",
+            plan
+        )
+    };
+    let (plan, buffer) = (plan.trim(), buffer.trim());
+    let full = format!("{}{}", head(plan), buffer);
+    if full.chars().count() <= max_chars {
+        return full;
     }
+    let notice = |n: usize| {
+        format!(
+            "\n[{} characters truncated. Keep the scratch paper compact.]",
+            n
+        )
+    };
+    let (plan_chars, buffer_chars) = (plan.chars().count(), buffer.chars().count());
+    // Room for the notice is taken with the most digits it could have.
+    let budget = max_chars.saturating_sub(
+        head("").chars().count() + notice(plan_chars + buffer_chars).chars().count(),
+    );
+    let plan_kept = plan_chars.min(budget);
+    let buffer_kept = buffer_chars.min(budget - plan_kept);
+    format!(
+        "{}{}{}",
+        head(&plan.chars().take(plan_kept).collect::<String>()),
+        buffer.chars().take(buffer_kept).collect::<String>(),
+        notice(plan_chars + buffer_chars - plan_kept - buffer_kept)
+    )
 }
 
 impl Into<Draft> for ToolBuilder {
     fn into(self) -> Draft {
         Draft {
             base: self,
-            buffer: "".to_string(),
-            plan: "1. Review task target and make possible planning.".to_string(),
+            buffer: Mutex::new("".to_string()),
+            plan: Mutex::new("1. Review task target and make possible planning.".to_string()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_truncates_notes() {
+        let full = render("1. explore", "cargo found", 1000);
+        assert!(full.contains("## Plan\n1. explore"));
+        assert!(full.ends_with("## Notes\ncargo found"));
+
+        let notes = "x".repeat(1000);
+        let cut = render("1. explore", &notes, 300);
+        assert!(cut.contains("## Plan\n1. explore"));
+        assert!(cut.contains("characters truncated"));
+        assert!(cut.chars().filter(|c| *c == 'x').count() < 300);
+        assert!(cut.chars().count() <= 300);
+
+        let plan = "p".repeat(1000);
+        let cut = render(&plan, "cargo found", 300);
+        assert!(cut.chars().count() <= 300);
+        assert!(cut.contains("## Notes\n\n["));
+        assert!(cut.contains("characters truncated"));
+    }
+}
@@ -1,10 +1,20 @@
+use std::sync::Mutex;
+
 use async_trait::async_trait;
 
-use crate::utils::ToolCallingError;
+use crate::utils::{ToolCallingError, ToolRestoringError};
 
 use super::{Tool, ToolBuilder};
 
 pub struct HumanIntervene {
+    base: ToolBuilder,
+    content: Mutex<String>,
+    response: Mutex<String>,
+}
+
+// Kept in checkpoint, so a pending help request survives a restart.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct State {
     content: String,
     response: String,
 }
@@ -22,7 +32,7 @@ struct Response {
 #[async_trait]
 impl Tool for HumanIntervene {
     fn name(&self) -> &str {
-        "HumanIntervene"
+        &self.base.name
     }
 
     fn tooldoc(&self) -> serde_json::Value {
@@ -46,43 +56,50 @@ impl Tool for HumanIntervene {
         })
     }
 
-    async fn call(&mut self, arg_string: String) -> Result<String, crate::utils::ToolCallingError> {
+    async fn call(&self, arg_string: String) -> Result<String, crate::utils::ToolCallingError> {
         let call_args: CallArgs = serde_json::from_str(&arg_string).map_err(|e| {
-            crate::utils::ToolCallingError::new(format!(
-                "Calling {} error: {}",
-                self.name(),
-                e.to_string()
-            ))
+            crate::utils::ToolCallingError::new(format!("Calling {} error: {}", self.name(), e))
         })?;
 
-        self.content = call_args.help;
+        *self.content.lock().unwrap() = call_args.help;
 
         // TODO: request external tools.
         // TODO: How to wakeup certain async future from outside interruption?
 
         let resp = Response {
-            response: self.response.clone(),
+            response: self.response.lock().unwrap().clone(),
         };
         serde_json::to_string(&resp)
             .map_err(|e| ToolCallingError::new(format!("Error marshalling response: {}", e)))
     }
 
-    fn fork(&self, args: Vec<String>) -> Result<Box<dyn Tool>, crate::utils::ToolForkingError> {
-        Ok(Box::new(HumanIntervene::default()))
+    fn fork(&self, _args: Vec<String>) -> Result<Box<dyn Tool>, crate::utils::ToolForkingError> {
+        Ok(Box::new(Into::<HumanIntervene>::into(self.base.clone())))
     }
-}
 
-impl Into<HumanIntervene> for ToolBuilder {
-    fn into(self) -> HumanIntervene {
-        HumanIntervene::default()
+    fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!(State {
+            content: self.content.lock().unwrap().clone(),
+            response: self.response.lock().unwrap().clone(),
+        })
+    }
+
+    fn restore(&self, state: serde_json::Value) -> Result<(), ToolRestoringError> {
+        let state: State = serde_json::from_value(state).map_err(|e| {
+            ToolRestoringError::new(format!("Restoring {} error: {}", self.name(), e))
+        })?;
+        *self.content.lock().unwrap() = state.content;
+        *self.response.lock().unwrap() = state.response;
+        Ok(())
     }
 }
 
-impl Default for HumanIntervene {
-    fn default() -> Self {
+impl Into<HumanIntervene> for ToolBuilder {
+    fn into(self) -> HumanIntervene {
         HumanIntervene {
-            content: "".to_string(),
-            response: "".to_string(),
+            base: self,
+            content: Mutex::new("".to_string()),
+            response: Mutex::new("".to_string()),
         }
     }
 }
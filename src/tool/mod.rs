@@ -3,7 +3,7 @@ pub mod human;
 pub mod result;
 pub mod shell;
 
-use crate::utils::{ToolCallingError, ToolForkingError};
+use crate::utils::{ToolCallingError, ToolForkingError, ToolRestoringError};
 use async_trait::async_trait;
 use draft::Draft;
 use human::HumanIntervene;
@@ -33,16 +33,46 @@ pub fn available_tools() -> Vec<Box<dyn Tool>> {
     ]
 }
 
+// Tools are shared by concurrent tool calls of the same turn, so state is kept behind
+// interior mutability and `call` takes `&self`.
 #[async_trait]
-pub trait Tool {
+pub trait Tool: Send + Sync {
     fn name(&self) -> &str;
     fn tooldoc(&self) -> serde_json::Value;
-    async fn call(&mut self, arg_string: String) -> Result<String, ToolCallingError>;
+    async fn call(&self, arg_string: String) -> Result<String, ToolCallingError>;
     // Fork tool from runtime version to task version.
     fn fork(&self, args: Vec<String>) -> Result<Box<dyn Tool>, ToolForkingError>;
+
+    // Function name declared in tooldoc. Tool calls from LLM are dispatched by it.
+    fn function_name(&self) -> String {
+        self.tooldoc()["function"]["name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    // Some(is_success) once the tool decides that the task should end.
+    fn ending(&self) -> Option<bool> {
+        None
+    }
+
+    // Content to be rendered into system prompt on every iteration.
+    fn prompt(&self) -> Option<String> {
+        None
+    }
+
+    // Task specific state to be kept in checkpoint. Stateless tools keep nothing.
+    fn snapshot(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    // Recover task specific state from snapshot.
+    fn restore(&self, _state: serde_json::Value) -> Result<(), ToolRestoringError> {
+        Ok(())
+    }
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct ToolBuilder {
     pub name: String,
     pub args: Vec<String>, // Args to be invoked in the task configuration.
@@ -1,9 +1,21 @@
+use std::sync::Mutex;
+
 use async_trait::async_trait;
 
+use crate::utils::ToolRestoringError;
+
 use super::{Tool, ToolBuilder};
 
 pub struct TaskEnds {
-    status: bool,
+    base: ToolBuilder,
+    status: Mutex<Option<bool>>,
+    result: Mutex<String>,
+}
+
+// Kept in checkpoint.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct State {
+    status: Option<bool>,
     result: String,
 }
 
@@ -16,7 +28,7 @@ struct CallArgs {
 #[async_trait]
 impl Tool for TaskEnds {
     fn name(&self) -> &str {
-        "TaskEnds"
+        &self.base.name
     }
 
     fn tooldoc(&self) -> serde_json::Value {
@@ -44,37 +56,53 @@ impl Tool for TaskEnds {
         })
     }
 
-    async fn call(&mut self, arg_string: String) -> Result<String, crate::utils::ToolCallingError> {
+    async fn call(&self, arg_string: String) -> Result<String, crate::utils::ToolCallingError> {
         let call_args: CallArgs = serde_json::from_str(&arg_string).map_err(|e| {
-            crate::utils::ToolCallingError::new(format!(
-                "Calling {} error: {}",
-                self.name(),
-                e.to_string()
-            ))
+            crate::utils::ToolCallingError::new(format!("Calling {} error: {}", self.name(), e))
         })?;
 
-        self.status = call_args.is_success;
-        self.result = call_args.explanation;
+        log::info!(
+            "Task ends with status {}: {}",
+            call_args.is_success,
+            call_args.explanation
+        );
+        *self.status.lock().unwrap() = Some(call_args.is_success);
+        *self.result.lock().unwrap() = call_args.explanation;
 
-        Ok(format!("Task ended with status: {}", self.status))
+        Ok(format!("Task ended with status: {}", call_args.is_success))
     }
 
-    fn fork(&self, args: Vec<String>) -> Result<Box<dyn Tool>, crate::utils::ToolForkingError> {
-        Ok(Box::new(TaskEnds::default()))
+    fn fork(&self, _args: Vec<String>) -> Result<Box<dyn Tool>, crate::utils::ToolForkingError> {
+        Ok(Box::new(Into::<TaskEnds>::into(self.base.clone())))
     }
-}
 
-impl Into<TaskEnds> for ToolBuilder {
-    fn into(self) -> TaskEnds {
-        TaskEnds::default()
+    fn ending(&self) -> Option<bool> {
+        *self.status.lock().unwrap()
+    }
+
+    fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!(State {
+            status: *self.status.lock().unwrap(),
+            result: self.result.lock().unwrap().clone(),
+        })
+    }
+
+    fn restore(&self, state: serde_json::Value) -> Result<(), ToolRestoringError> {
+        let state: State = serde_json::from_value(state).map_err(|e| {
+            ToolRestoringError::new(format!("Restoring {} error: {}", self.name(), e))
+        })?;
+        *self.status.lock().unwrap() = state.status;
+        *self.result.lock().unwrap() = state.result;
+        Ok(())
     }
 }
 
-impl Default for TaskEnds {
-    fn default() -> Self {
+impl Into<TaskEnds> for ToolBuilder {
+    fn into(self) -> TaskEnds {
         TaskEnds {
-            status: false,
-            result: "".to_string(),
+            base: self,
+            status: Mutex::new(None),
+            result: Mutex::new("".to_string()),
         }
     }
 }
@@ -5,7 +5,8 @@ use async_trait::async_trait;
 
 use crate::tool::Tool;
 use crate::utils::{ShellRunningError, ToolCallingError};
-use std::process::{Command, Output};
+use std::process::Output;
+use tokio::process::Command;
 
 use super::ToolBuilder;
 
@@ -65,16 +66,12 @@ impl Tool for Shell {
         })
     }
 
-    async fn call(&mut self, arg_string: String) -> Result<String, ToolCallingError> {
+    async fn call(&self, arg_string: String) -> Result<String, ToolCallingError> {
         let call_args: CallArgs = serde_json::from_str(&arg_string).map_err(|e| {
-            crate::utils::ToolCallingError::new(format!(
-                "Calling {} error: {}",
-                self.name(),
-                e.to_string()
-            ))
+            crate::utils::ToolCallingError::new(format!("Calling {} error: {}", self.name(), e))
         })?;
 
-        match self.run(call_args.executable, call_args.args) {
+        match self.run(call_args.executable, call_args.args).await {
             Ok((stdout, _stderr, _status_code)) => {
                 let resp = Response {
                     stdout,
@@ -89,7 +86,7 @@ impl Tool for Shell {
         }
     }
 
-    fn fork(&self, args: Vec<String>) -> Result<Box<dyn Tool>, crate::utils::ToolForkingError> {
+    fn fork(&self, _args: Vec<String>) -> Result<Box<dyn Tool>, crate::utils::ToolForkingError> {
         // TODO: Start a new sandbox and yield the shell.
         Ok(Box::new(self.clone()))
     }
@@ -102,18 +99,21 @@ impl Into<Shell> for ToolBuilder {
 }
 
 impl Shell {
-    pub fn run(
-        &mut self,
+    pub async fn run(
+        &self,
         exe: String,
         args: Vec<String>,
     ) -> Result<(String, String, i32), ShellRunningError> {
-        if args.len() == 0 {
+        if args.is_empty() {
             return Err(ShellRunningError::new("zero shell command.".to_string()));
         }
 
+        // Killed if the call is abandoned, e.g. timed out.
         let output: Output = Command::new(exe)
             .args(&args)
+            .kill_on_drop(true)
             .output()
+            .await
             .map_err(|e| ShellRunningError::new(format!("Failed to execute command: {}", e)))?;
 
         let stdout = String::from_utf8_lossy(&output.stdout).to_string();
@@ -131,7 +131,7 @@ mod test {
 
     #[tokio::test]
     async fn test_run_echo_command() {
-        let mut shell: Shell = ToolBuilder {
+        let shell: Shell = ToolBuilder {
             name: "shell".to_string(),
             args: vec![],
         }
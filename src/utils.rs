@@ -1,5 +1,6 @@
 use simple_logger::SimpleLogger;
 use std::fmt;
+use std::time::Duration;
 
 // Model picking and Tool binding error.
 pub type ModelNotRegistered = Errorbase;
@@ -7,15 +8,19 @@ pub type ToolNotRegistered = Errorbase;
 pub type ProviderNotRegistered = Errorbase;
 
 // Service Error.
-pub type ProviderError = Errorbase;
 pub type ProviderResponseUnmarshalError = Errorbase;
 pub type ProviderResponseError = Errorbase;
 
 // ToolCalls Error.
 pub type ToolCallingError = Errorbase;
 pub type ToolForkingError = Errorbase;
+pub type ToolRestoringError = Errorbase;
 pub type ShellRunningError = Errorbase;
 
+// Runtime Error.
+pub type TranscriptError = Errorbase;
+pub type CheckpointError = Errorbase;
+
 #[derive(Debug)]
 pub struct Errorbase {
     content: String,
@@ -35,6 +40,77 @@ impl fmt::Display for Errorbase {
 
 impl std::error::Error for Errorbase {}
 
+// Service Error. Variants tell apart failures worth a retry.
+#[derive(Debug)]
+pub enum ProviderError {
+    // Service unreachable, or connection broken before the response ended.
+    Transport(String),
+    // Service answered with a non-success status, with the delay it wants before a retry if
+    // given, e.g. on 503.
+    Status {
+        status: u16,
+        retry_after: Option<Duration>,
+        body: String,
+    },
+    // Service asked to slow down, with the delay it wants if given.
+    RateLimited {
+        retry_after: Option<Duration>,
+        body: String,
+    },
+    // Service answered with something not understood.
+    Decode(String),
+    // Service is configured wrong, e.g. API key missing.
+    Config(String),
+    // Response cache failed, or has no response to replay.
+    Cache(String),
+}
+
+impl ProviderError {
+    pub fn retryable(&self) -> bool {
+        match self {
+            ProviderError::Transport(_) | ProviderError::RateLimited { .. } => true,
+            ProviderError::Status { status, .. } => *status >= 500,
+            ProviderError::Decode(_) | ProviderError::Config(_) | ProviderError::Cache(_) => false,
+        }
+    }
+
+    // Delay the service asked for before a retry.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ProviderError::Status { retry_after, .. }
+            | ProviderError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::Transport(e) => write!(f, "Transport error: {}", e),
+            ProviderError::Status { status, body, .. } => {
+                write!(f, "HTTP status {}: {}", status, body)
+            }
+            ProviderError::RateLimited { retry_after, body } => match retry_after {
+                Some(delay) => write!(f, "Rate limited, retry after {:?}: {}", delay, body),
+                None => write!(f, "Rate limited: {}", body),
+            },
+            ProviderError::Decode(e) => write!(f, "Decode error: {}", e),
+            ProviderError::Config(e) => write!(f, "Configuration error: {}", e),
+            ProviderError::Cache(e) => write!(f, "Cache error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+// 64-bit FNV-1a, the same on every platform and release.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
+}
+
 pub fn log_init() {
     SimpleLogger::new().init().unwrap();
     log::info!("Initiated logger.")